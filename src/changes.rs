@@ -0,0 +1,97 @@
+use std::{io, fs};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::manifest::Manifest;
+
+/// A file gsub wrote to, and (if we're keeping them) what it looked like beforehand
+pub struct Change {
+    pub path: PathBuf,
+    pub original: Option<String>,
+}
+
+/// Every file touched during a run
+pub struct ChangeLog {
+    keep_originals: bool,
+    changes: Mutex<Vec<Change>>,
+    /// Where `--copy-on-write` copies are remembered between runs
+    manifest: Option<Manifest>,
+}
+
+impl ChangeLog {
+    pub fn new(keep_originals: bool) -> Self {
+        Self { keep_originals, changes: Mutex::new(Vec::new()), manifest: None }
+    }
+
+    pub fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Records a copy made of `original`, so later runs know gsub made it
+    pub fn record_copy(&self, copy: &Path, original: &Path, contents: String) {
+        if let Some(manifest) = &self.manifest {
+            manifest.record(copy, original);
+        }
+        self.record(copy, contents);
+    }
+
+    pub fn save_manifest(&self) -> io::Result<()> {
+        self.manifest.as_ref().map_or(Ok(()), Manifest::save)
+    }
+
+    pub fn record(&self, path: impl AsRef<Path>, original: String) {
+        let change = Change {
+            path: path.as_ref().to_path_buf(),
+            original: if self.keep_originals { Some(original) } else { None },
+        };
+        self.changes.lock().unwrap().push(change);
+    }
+
+    /// Every changed path, sorted so the output doesn't depend on the walk order
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.changes.lock().unwrap()
+            .iter()
+            .map(|c| c.path.clone())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    /// Writes the original contents back to every changed file we kept them for.
+    pub fn rollback(&self) -> Vec<(PathBuf, io::Result<()>)> {
+        self.changes.lock().unwrap()
+            .iter()
+            .filter_map(|c| c.original.as_ref().map(|orig| (c.path.clone(), fs::write(&c.path, orig))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_back_changed_files_to_their_original_contents() {
+        let path = "test-files/changes-rollback";
+        fs::write(path, "soy milk").unwrap();
+        let changes = ChangeLog::new(true);
+        changes.record(path, "oat milk".to_string());
+        let results = changes.rollback();
+        let contents = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+        assert_eq!(contents, "oat milk");
+    }
+
+    #[test]
+    fn does_not_roll_back_without_originals() {
+        let changes = ChangeLog::new(false);
+        changes.record("test-files/never-written", "oat milk".to_string());
+        assert!(changes.rollback().is_empty());
+    }
+}
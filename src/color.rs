@@ -0,0 +1,154 @@
+use std::env;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use crate::paths::absolute;
+
+static RESET: &str = "\x1b[0m";
+static PATH: &str = "\x1b[35m";
+static HIGHLIGHT: &str = "\x1b[1;31m";
+static ERROR: &str = "\x1b[31m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "never"];
+
+    /// Whether to color output going to a stream, given whether that stream is a terminal
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice '{}'", s)),
+        }
+    }
+}
+
+/// Styles text for a terminal. A disabled `Painter` hands everything back untouched.
+#[derive(Debug, Clone)]
+pub struct Painter {
+    enabled: bool,
+    hostname: String,
+}
+
+impl Painter {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, hostname: if enabled { hostname() } else { String::new() } }
+    }
+
+    pub fn plain() -> Self { Self::new(false) }
+
+    /// A colored path, which is also an OSC 8 hyperlink to the file (and line, if given)
+    pub fn path(&self, path: &Path, line: Option<usize>) -> String {
+        if !self.enabled {
+            return path.display().to_string();
+        }
+        let mut url = format!("file://{}{}", self.hostname, percent_encode(&uri_path(path)));
+        if let Some(line) = line {
+            url.push_str(&format!("#L{}", line));
+        }
+        format!("\x1b]8;;{}\x1b\\{}{}{}\x1b]8;;\x1b\\", url, PATH, path.display(), RESET)
+    }
+
+    /// Text that matched the pattern
+    pub fn highlight(&self, text: impl Display) -> String {
+        self.paint(HIGHLIGHT, text)
+    }
+
+    pub fn error(&self, text: impl Display) -> String {
+        self.paint(ERROR, text)
+    }
+
+    fn paint(&self, style: &str, text: impl Display) -> String {
+        if self.enabled {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// A `file://` URI for `path`, as editors and language servers expect them
+pub fn file_uri(path: &Path) -> String {
+    format!("file://{}", percent_encode(&uri_path(path)))
+}
+
+/// `path` made absolute, with `.` and `..` resolved, since editors match files up by their URIs
+fn uri_path(path: &Path) -> String {
+    let abs = env::current_dir()
+        .map(|cwd| absolute(&cwd, path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    abs.to_string_lossy().replace('\\', "/")
+}
+
+fn percent_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            },
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String { String::new() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_painter_leaves_text_alone() {
+        let p = Painter::plain();
+        assert_eq!(p.path(Path::new("src/main.rs"), Some(3)), "src/main.rs");
+        assert_eq!(p.highlight("gabagool"), "gabagool");
+    }
+
+    #[test]
+    fn hyperlinks_paths_to_file_urls() {
+        let p = Painter { enabled: true, hostname: "laptop".to_string() };
+        let linked = p.path(Path::new("/tmp/oat milk.txt"), Some(12));
+        assert_eq!(
+            linked,
+            "\x1b]8;;file://laptop/tmp/oat%20milk.txt#L12\x1b\\\x1b[35m/tmp/oat milk.txt\x1b[0m\x1b]8;;\x1b\\"
+        );
+    }
+
+    #[test]
+    fn resolves_dots_in_file_urls() {
+        let cwd = env::current_dir().unwrap();
+        let expected = format!("file://{}", percent_encode(&cwd.join("src/m.txt").to_string_lossy()));
+        assert_eq!(file_uri(Path::new("./src/../src/m.txt")), expected);
+    }
+}
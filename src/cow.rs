@@ -0,0 +1,170 @@
+use std::{fs, io};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use ignore::WalkBuilder;
+use crate::CURRENT_DIR;
+use crate::event::{GsubEvent, SkipReason};
+use crate::gsub::cow_path;
+use crate::guard::VCS_DIRS;
+use crate::manifest::Manifest;
+use crate::patch::unified_diff;
+
+/// The file a `--copy-on-write` copy named with `suffix` was made from
+pub fn original_of(copy: &Path, suffix: &str) -> Option<PathBuf> {
+    let name = copy.file_name()?.to_str()?;
+    let original = name.strip_suffix(suffix)?.strip_suffix('.').filter(|n| !n.is_empty())?;
+    Some(copy.with_file_name(original))
+}
+
+/// Every `(copy, original)` pair under `roots`, sorted by the original. Copies are found next
+/// to the files they were made from, and in the manifests there, which also know about
+/// numbered copies. Hidden and ignored files are searched too, since gsub might have been told
+/// to edit them, and ignore files often ignore `*.gsub`.
+pub fn find_copies(roots: &[PathBuf], suffix: &str, manifest: &Manifest) -> Vec<(PathBuf, PathBuf)> {
+    let mut paths = roots.iter();
+    let mut wb = WalkBuilder::new(paths.next().map_or(Path::new(CURRENT_DIR), |p| p.as_path()));
+    paths.for_each(|p| { wb.add(p); });
+    wb.standard_filters(false)
+        .filter_entry(|e| !VCS_DIRS.iter().any(|dir| e.file_name() == *dir));
+
+    // (original, copy), so they come out sorted and numbered copies all count
+    let mut copies = BTreeSet::new();
+    for entry in wb.follow_links(false).build().filter_map(Result::ok) {
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        let path = entry.path();
+        if let Some(original) = manifest.original_of(path) {
+            copies.insert((original, path.to_path_buf()));
+            continue;
+        }
+        if manifest.made(path) {
+            continue;
+        }
+        if let Some(original) = original_of(path, suffix) {
+            // Only named copies count, otherwise we'd find copies of copies
+            if entry.depth() == 0 {
+                copies.insert((original, path.to_path_buf()));
+            }
+            continue;
+        }
+        for copy in manifest.copies_of(path) {
+            copies.insert((path.to_path_buf(), copy));
+        }
+        let copy = cow_path(path, suffix, 0);
+        if copy.is_file() && !manifest.made(&copy) {
+            copies.insert((path.to_path_buf(), copy));
+        }
+    }
+    copies.into_iter().map(|(original, copy)| (copy, original)).collect()
+}
+
+/// Replaces `original` with `copy` in one rename, so nothing ever sees half of either
+pub fn accept(copy: &Path, original: &Path) -> Result<GsubEvent, GsubEvent> {
+    let failed = |e: io::Error| GsubEvent::WriteFailed { path: original.to_path_buf(), kind: e.kind() };
+    // The copy was created with default permissions, so give it the original's
+    if let Ok(md) = fs::metadata(original) {
+        fs::set_permissions(copy, md.permissions()).map_err(failed)?;
+    }
+    fs::rename(copy, original).map_err(failed)?;
+    Ok(GsubEvent::Accepted { path: original.to_path_buf(), copy: copy.to_path_buf() })
+}
+
+pub fn discard(copy: &Path) -> Result<GsubEvent, GsubEvent> {
+    fs::remove_file(copy)
+        .map_err(|e| GsubEvent::WriteFailed { path: copy.to_path_buf(), kind: e.kind() })?;
+    Ok(GsubEvent::Discarded { path: copy.to_path_buf() })
+}
+
+/// How `copy` differs from `original`, as a patch of `original`
+pub fn diff(copy: &Path, original: &Path) -> Result<GsubEvent, GsubEvent> {
+    let read = |path: &Path| fs::read_to_string(path).map_err(|e| GsubEvent::Skipped {
+        path: path.to_path_buf(),
+        reason: SkipReason::from_read_error(&e),
+    });
+    // A missing original just means every line is new
+    let before = if original.exists() { read(original)? } else { String::new() };
+    let after = read(copy)?;
+    if before == after {
+        return Ok(GsubEvent::Unchanged { path: original.to_path_buf() });
+    }
+    Ok(GsubEvent::Diffed { path: original.to_path_buf(), diff: unified_diff(original, &before, &after) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_originals_of_copies() {
+        let original_of = |copy| original_of(Path::new(copy), "gsub");
+        assert_eq!(original_of("dir/gabagool.txt.gsub"), Some(PathBuf::from("dir/gabagool.txt")));
+        assert_eq!(original_of("gabagool.gsub"), Some(PathBuf::from("gabagool")));
+        assert_eq!(original_of(".gsub"), None);
+        assert_eq!(original_of("gabagoolgsub"), None);
+        assert_eq!(original_of("gabagool.txt"), None);
+    }
+
+    #[test]
+    fn accepts_copies_over_their_originals() {
+        fs::create_dir_all("test-files/cow-accept").unwrap();
+        fs::write("test-files/cow-accept/milk.txt", "soy milk").unwrap();
+        fs::write("test-files/cow-accept/milk.txt.gsub", "oat milk").unwrap();
+        let manifest = Manifest::new(&std::env::current_dir().unwrap());
+        let copies = find_copies(&[PathBuf::from("test-files/cow-accept")], "gsub", &manifest);
+        assert_eq!(copies, vec![(
+            PathBuf::from("test-files/cow-accept/milk.txt.gsub"),
+            PathBuf::from("test-files/cow-accept/milk.txt"),
+        )]);
+        let (copy, original) = &copies[0];
+        assert!(accept(copy, original).is_ok());
+        let contents = fs::read_to_string(original).unwrap();
+        let copy_exists = copy.exists();
+        fs::remove_dir_all("test-files/cow-accept").unwrap();
+        assert_eq!(contents, "oat milk");
+        assert!(!copy_exists);
+    }
+
+    #[test]
+    fn finds_hidden_and_ignored_copies_to_discard() {
+        fs::create_dir_all("test-files/cow-discard").unwrap();
+        fs::write("test-files/cow-discard/.ignore", "*.gsub\n").unwrap();
+        fs::write("test-files/cow-discard/.milk.txt", "soy milk").unwrap();
+        fs::write("test-files/cow-discard/.milk.txt.gsub", "oat milk").unwrap();
+        let manifest = Manifest::new(&std::env::current_dir().unwrap());
+        let copies = find_copies(&[PathBuf::from("test-files/cow-discard")], "gsub", &manifest);
+        let discarded = copies.iter().map(|(copy, _)| discard(copy)).collect::<Vec<_>>();
+        let copy_exists = Path::new("test-files/cow-discard/.milk.txt.gsub").exists();
+        let contents = fs::read_to_string("test-files/cow-discard/.milk.txt").unwrap();
+        fs::remove_dir_all("test-files/cow-discard").unwrap();
+        assert_eq!(copies, vec![(
+            PathBuf::from("test-files/cow-discard/.milk.txt.gsub"),
+            PathBuf::from("test-files/cow-discard/.milk.txt"),
+        )]);
+        assert!(discarded.iter().all(Result::is_ok));
+        assert!(!copy_exists);
+        assert_eq!(contents, "soy milk");
+    }
+
+    #[test]
+    fn diffs_copies_against_their_originals() {
+        fs::create_dir_all("test-files/cow-diff").unwrap();
+        fs::write("test-files/cow-diff/milk.txt", "soy milk\n").unwrap();
+        fs::write("test-files/cow-diff/milk.txt.gsub", "oat milk\n").unwrap();
+        fs::write("test-files/cow-diff/new.txt.gsub", "rice milk\n").unwrap();
+        let diffed = diff(Path::new("test-files/cow-diff/milk.txt.gsub"), Path::new("test-files/cow-diff/milk.txt"));
+        let created = diff(Path::new("test-files/cow-diff/new.txt.gsub"), Path::new("test-files/cow-diff/new.txt"));
+        let unchanged = diff(Path::new("test-files/cow-diff/milk.txt"), Path::new("test-files/cow-diff/milk.txt"));
+        fs::remove_dir_all("test-files/cow-diff").unwrap();
+
+        match diffed {
+            Ok(GsubEvent::Diffed { diff, .. }) => assert!(diff.contains("-soy milk\n+oat milk\n")),
+            other => panic!("expected a diff, got {:?}", other),
+        }
+        match created {
+            Ok(GsubEvent::Diffed { diff, .. }) => assert!(diff.contains("@@ -0,0 +1 @@\n+rice milk\n")),
+            other => panic!("expected a diff, got {:?}", other),
+        }
+        assert!(matches!(unchanged, Ok(GsubEvent::Unchanged { .. })));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::Serialize;
+use crate::color::file_uri;
+use crate::proposals::Proposals;
+use crate::replacer::{Replacement, Replacer};
+
+/// A `Position` in the Language Server Protocol: 0-based, counting UTF-16 code units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LspPosition {
+    pub line: usize,
+    pub character: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+    pub range: LspRange,
+    pub new_text: String,
+}
+
+/// Converts byte offsets in some text to lines and columns
+struct LineIndex<'t> {
+    text: &'t str,
+    line_starts: Vec<usize>,
+}
+
+impl<'t> LineIndex<'t> {
+    fn new(text: &'t str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Self { text, line_starts }
+    }
+
+    /// 0-based line, and the offset of its start
+    fn line_of(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        (line, self.line_starts[line])
+    }
+
+    fn lsp_position(&self, offset: usize) -> LspPosition {
+        let (line, start) = self.line_of(offset);
+        LspPosition { line, character: self.text[start..offset].encode_utf16().count() }
+    }
+}
+
+fn text_edits(contents: &str, replacer: &Replacer) -> Vec<TextEdit> {
+    let index = LineIndex::new(contents);
+    replacer.expansions(contents)
+        .into_iter()
+        .map(|e| TextEdit {
+            range: LspRange {
+                start: index.lsp_position(e.range.start),
+                end: index.lsp_position(e.range.end),
+            },
+            new_text: e.text,
+        })
+        .collect()
+}
+
+/// Collects an LSP `WorkspaceEdit` of every change, for `--format lsp-workspace-edit`
+#[derive(Default)]
+pub struct WorkspaceEdits {
+    changes: Mutex<BTreeMap<String, Vec<TextEdit>>>,
+}
+
+impl WorkspaceEdits {
+    pub fn new() -> Self { Self::default() }
+}
+
+#[derive(Serialize)]
+struct WorkspaceEdit<'a> {
+    changes: &'a BTreeMap<String, Vec<TextEdit>>,
+}
+
+impl Proposals for WorkspaceEdits {
+    fn propose(&self, path: &Path, replacement: &Replacement, replacer: &Replacer) {
+        let edits = text_edits(&replacement.original, replacer);
+        self.changes.lock().unwrap().insert(file_uri(path), edits);
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        let changes = self.changes.lock().unwrap();
+        serde_json::to_writer(&mut *out, &WorkspaceEdit { changes: &changes })?;
+        writeln!(out)?;
+        out.flush()
+    }
+}
+
+/// Collects every changed location as `path:line:column: message` lines, which vim's and
+/// emacs' default error formats understand, for `--format quickfix`
+#[derive(Default)]
+pub struct Quickfix {
+    entries: Mutex<Vec<(PathBuf, Vec<String>)>>,
+}
+
+impl Quickfix {
+    pub fn new() -> Self { Self::default() }
+}
+
+/// Keeps a quickfix message on one line
+fn one_line(text: &str) -> String {
+    text.replace('\r', "\\r").replace('\n', "\\n")
+}
+
+impl Proposals for Quickfix {
+    fn propose(&self, path: &Path, replacement: &Replacement, replacer: &Replacer) {
+        let contents = &replacement.original;
+        let index = LineIndex::new(contents);
+        let entries = replacer.expansions(contents)
+            .into_iter()
+            .map(|e| {
+                let (line, start) = index.line_of(e.range.start);
+                format!(
+                    "{}:{}:{}: replace `{}` with `{}`",
+                    path.display(),
+                    line + 1,
+                    e.range.start - start + 1,
+                    one_line(&contents[e.range]),
+                    one_line(&e.text),
+                )
+            })
+            .collect();
+        self.entries.lock().unwrap().push((path.to_path_buf(), entries));
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for line in entries.iter().flat_map(|(_, lines)| lines) {
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replacement(original: &str) -> Replacement {
+        Replacement {
+            original: original.to_string(),
+            replaced: String::new(),
+            replacements: 0,
+            first_line: 1,
+        }
+    }
+
+    #[test]
+    fn lsp_positions_count_utf16_code_units() {
+        let replacer = Replacer::new("milk", "cream").unwrap();
+        let edits = text_edits("café\n🥛 milk", &replacer);
+        assert_eq!(edits, vec![TextEdit {
+            range: LspRange {
+                start: LspPosition { line: 1, character: 3 },
+                end: LspPosition { line: 1, character: 7 },
+            },
+            new_text: "cream".to_string(),
+        }]);
+        assert_eq!(
+            serde_json::to_string(&edits[0]).unwrap(),
+            r#"{"range":{"start":{"line":1,"character":3},"end":{"line":1,"character":7}},"newText":"cream"}"#
+        );
+    }
+
+    #[test]
+    fn lists_quickfix_locations_with_byte_columns() {
+        let replacer = Replacer::new(r"milk\n", "cream ").unwrap();
+        let quickfix = Quickfix::new();
+        quickfix.propose(Path::new("café.txt"), &replacement("oat milk\nsoy milk\n"), &replacer);
+        let mut out = Vec::new();
+        quickfix.write_to(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+café.txt:1:5: replace `milk\\n` with `cream `
+café.txt:2:5: replace `milk\\n` with `cream `
+");
+    }
+}
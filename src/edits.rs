@@ -0,0 +1,281 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::event::{GsubEvent, SkipReason};
+use crate::file_data::{OpenFileData, OverWrite, SizedReader};
+
+/// An edit made by some other tool, for `gsub apply-edits`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ExternalEdit {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub range: EditRange,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum EditRange {
+    /// Byte offsets into the file
+    Bytes { start: usize, end: usize },
+    Lines { start: Position, end: Position },
+}
+
+/// 1-based, like the lines and columns `--search` prints. Columns count bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The parts of a ripgrep `--json` match message we need
+#[derive(Debug, Deserialize)]
+struct RgMatch {
+    path: RgData,
+    absolute_offset: usize,
+    submatches: Vec<RgSubmatch>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RgSubmatch {
+    start: usize,
+    end: usize,
+}
+
+/// ripgrep writes paths that aren't UTF-8 as base64
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RgData {
+    Text { text: String },
+    Bytes { bytes: String },
+}
+
+impl RgData {
+    /// The path, or as much of it as can be shown if it can't be decoded
+    fn path(&self) -> Result<PathBuf, PathBuf> {
+        let bytes = match self {
+            RgData::Text { text } => return Ok(PathBuf::from(text)),
+            RgData::Bytes { bytes } => STANDARD.decode(bytes).map_err(|_| PathBuf::from(bytes))?,
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+        }
+        #[cfg(not(unix))]
+        String::from_utf8(bytes)
+            .map(PathBuf::from)
+            .map_err(|e| PathBuf::from(String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    }
+}
+
+/// ripgrep's `--json` messages that aren't matches, which are just information
+static RG_INFO_TYPES: &[&str] = &["begin", "end", "context", "summary"];
+
+/// Everything `read_edits` read
+#[derive(Debug, Default)]
+pub struct Edits {
+    pub edits: Vec<ExternalEdit>,
+    /// Files ripgrep matched in whose paths can't be decoded here
+    pub undecodable: BTreeSet<PathBuf>,
+}
+
+/// Reads edits as JSON: either arrays of them, one per line, or ripgrep's `--json` output.
+/// ripgrep's matches are all replaced with `replacement`.
+pub fn read_edits(reader: impl Read, replacement: Option<&str>) -> io::Result<Edits> {
+    let mut read = Edits::default();
+    for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        match value? {
+            Value::Array(values) => {
+                for v in values {
+                    read.edits.push(serde_json::from_value(v)?);
+                }
+            },
+            v if v["type"] == "match" => {
+                let m: RgMatch = serde_json::from_value(v["data"].clone())?;
+                match m.path.path() {
+                    Ok(path) => read.edits.extend(from_rg_match(path, m, replacement)?),
+                    Err(path) => { read.undecodable.insert(path); },
+                }
+            },
+            v if RG_INFO_TYPES.iter().any(|t| v["type"] == *t) => {},
+            v if v.get("type").is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown message type {}", v["type"]),
+                ));
+            },
+            v => read.edits.push(serde_json::from_value(v)?),
+        }
+    }
+    Ok(read)
+}
+
+fn from_rg_match(path: PathBuf, m: RgMatch, replacement: Option<&str>) -> io::Result<Vec<ExternalEdit>> {
+    let replacement = replacement.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "ripgrep matches need a --replacement")
+    })?;
+    let RgMatch { absolute_offset, submatches, .. } = m;
+    let edits = submatches.into_iter()
+        .map(|sub| {
+            let range = EditRange::Bytes {
+                start: absolute_offset + sub.start,
+                end: absolute_offset + sub.end,
+            };
+            ExternalEdit { path: path.clone(), range, replacement: replacement.to_string() }
+        })
+        .collect();
+    Ok(edits)
+}
+
+/// `contents` with every edit made, along with the line of the first one. Fails if any edits
+/// overlap, or don't fit in `contents`.
+pub fn apply_to(contents: &str, edits: &[ExternalEdit]) -> Option<(String, usize)> {
+    let mut line_starts = vec![0];
+    line_starts.extend(contents.match_indices('\n').map(|(i, _)| i + 1));
+    let offset = |p: Position| {
+        let line_start = *line_starts.get(p.line.checked_sub(1)?)?;
+        let line_end = line_starts.get(p.line).map_or(contents.len(), |&next| next - 1);
+        let offset = line_start + p.column.checked_sub(1)?;
+        if offset <= line_end { Some(offset) } else { None }
+    };
+    let mut resolved = edits.iter()
+        .map(|e| {
+            let range = match e.range {
+                EditRange::Bytes { start, end } => start..end,
+                EditRange::Lines { start, end } => offset(start)?..offset(end)?,
+            };
+            let fits = range.start <= range.end
+                && contents.is_char_boundary(range.start)
+                && contents.is_char_boundary(range.end);
+            if fits { Some((range, e.replacement.as_str())) } else { None }
+        })
+        .collect::<Option<Vec<(Range<usize>, &str)>>>()?;
+    resolved.sort_by_key(|(range, _)| (range.start, range.end));
+
+    let mut applied = String::with_capacity(contents.len());
+    let mut last_end = 0;
+    for (range, replacement) in &resolved {
+        if range.start < last_end {
+            return None;
+        }
+        applied.push_str(&contents[last_end..range.start]);
+        applied.push_str(replacement);
+        last_end = range.end;
+    }
+    applied.push_str(&contents[last_end..]);
+    let first_start = resolved.first().map_or(0, |(range, _)| range.start);
+    let first_line = line_starts.partition_point(|&start| start <= first_start);
+    Some((applied, first_line))
+}
+
+/// Makes `edits` to the file at `path`, with the same checks `gsub()` makes before writing
+pub fn apply_edits(
+    path: &Path,
+    edits: &[ExternalEdit],
+    opener: &impl OpenFileData,
+    dry_run: bool,
+) -> Result<GsubEvent, GsubEvent>
+{
+    let skipped = |reason| GsubEvent::Skipped { path: path.to_path_buf(), reason };
+    let mut fd = opener.open_path(path).map_err(|e| skipped(SkipReason::OpenFailed(e.kind())))?;
+    let contents = fd.read_all().map_err(|e| skipped(SkipReason::from_read_error(&e)))?;
+    let (applied, line) = apply_to(&contents, edits).ok_or_else(|| skipped(SkipReason::InvalidEdits))?;
+    let path = path.to_path_buf();
+    if applied == contents {
+        return Ok(GsubEvent::Unchanged { path });
+    }
+    let replacements = edits.len();
+    if dry_run {
+        return Ok(GsubEvent::WouldUpdate { path, replacements, line });
+    }
+    if !fd.unmodified_since_open().unwrap_or(false) {
+        return Err(skipped(SkipReason::ModifiedConcurrently));
+    }
+    fd.overwrite(applied.as_bytes())
+        .map_err(|e| GsubEvent::WriteFailed { path: path.clone(), kind: e.kind() })?;
+    Ok(GsubEvent::Updated { path, replacements, line })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(range: EditRange, replacement: &str) -> ExternalEdit {
+        ExternalEdit { path: PathBuf::from("milk.txt"), range, replacement: replacement.to_string() }
+    }
+
+    fn at(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn reads_edit_lists_and_ripgrep_matches() {
+        let input = r#"
+[{"path": "a.txt", "start": 0, "end": 3, "replacement": "oat"}]
+{"path": "b.txt", "start": {"line": 2, "column": 1}, "end": {"line": 2, "column": 4}, "replacement": "soy"}
+{"type":"begin","data":{"path":{"text":"c.txt"}}}
+{"type":"match","data":{"path":{"text":"c.txt"},"lines":{"text":"rice milk\n"},"line_number":3,"absolute_offset":20,"submatches":[{"match":{"text":"rice"},"start":0,"end":4}]}}
+"#;
+        let edits = read_edits(input.as_bytes(), Some("hemp")).unwrap().edits;
+        assert_eq!(edits.len(), 3);
+        assert_eq!(edits[0].range, EditRange::Bytes { start: 0, end: 3 });
+        assert_eq!(edits[1].range, EditRange::Lines { start: at(2, 1), end: at(2, 4) });
+        assert_eq!(edits[2], ExternalEdit {
+            path: PathBuf::from("c.txt"),
+            range: EditRange::Bytes { start: 20, end: 24 },
+            replacement: "hemp".to_string(),
+        });
+        assert!(read_edits(input.as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn reads_ripgrep_paths_that_arent_utf8() {
+        let input = r#"
+{"type":"match","data":{"path":{"bytes":"ZP8udHh0"},"absolute_offset":0,"submatches":[{"start":0,"end":4}]}}
+{"type":"match","data":{"path":{"bytes":"not base64!"},"absolute_offset":0,"submatches":[{"start":0,"end":4}]}}
+{"type":"summary","data":{}}
+"#;
+        let read = read_edits(input.as_bytes(), Some("hemp")).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            assert_eq!(read.edits.len(), 1);
+            assert_eq!(read.edits[0].path.as_os_str().as_bytes(), b"d\xff.txt");
+        }
+        assert!(read.undecodable.contains(Path::new("not base64!")));
+    }
+
+    #[test]
+    fn refuses_unknown_message_types() {
+        let input = r#"{"type":"replace","path":"a.txt","start":0,"end":3,"replacement":"oat"}"#;
+        assert!(read_edits(input.as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn applies_byte_and_line_column_ranges() {
+        let edits = [
+            edit(EditRange::Lines { start: at(2, 1), end: at(2, 4) }, "almond"),
+            edit(EditRange::Bytes { start: 0, end: 3 }, "oatly"),
+        ];
+        let (applied, line) = apply_to("oat milk\nsoy milk\n", &edits).unwrap();
+        assert_eq!(applied, "oatly milk\nalmond milk\n");
+        assert_eq!(line, 1);
+    }
+
+    #[test]
+    fn rejects_overlapping_and_out_of_range_edits() {
+        let overlapping = [
+            edit(EditRange::Bytes { start: 0, end: 5 }, "a"),
+            edit(EditRange::Bytes { start: 4, end: 8 }, "b"),
+        ];
+        assert_eq!(apply_to("oat milk", &overlapping), None);
+        let past_the_line = [edit(EditRange::Lines { start: at(1, 1), end: at(1, 20) }, "a")];
+        assert_eq!(apply_to("oat milk\nsoy milk", &past_the_line), None);
+    }
+}
@@ -0,0 +1,363 @@
+use std::{fmt, io};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Serializer};
+use crate::color::Painter;
+use crate::search::SearchLine;
+use crate::replacer::Extraction;
+
+/// Everything that can happen while running gsub, in a form that's easy to match on.
+/// `Presenter` turns these into text, JSON, etc.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GsubEvent {
+    /// `line` is where the first replacement was made
+    Updated { path: PathBuf, replacements: usize, line: usize },
+    WouldUpdate { path: PathBuf, replacements: usize, line: usize },
+    Created { path: PathBuf, original: PathBuf, replacements: usize, line: usize },
+    Unchanged { path: PathBuf },
+    /// An unchanged file copied into `--output-dir`
+    Copied { path: PathBuf, original: PathBuf },
+    Skipped { path: PathBuf, reason: SkipReason },
+    WriteFailed {
+        path: PathBuf,
+        #[serde(serialize_with = "error_kind")]
+        kind: io::ErrorKind,
+    },
+    HookFailed { command: String, status: Option<i32> },
+    VerifyFailed { command: String, status: Option<i32> },
+    /// The `--verify` command couldn't even be started
+    VerifyNotRun {
+        command: String,
+        #[serde(serialize_with = "error_kind")]
+        kind: io::ErrorKind,
+    },
+    Restored { path: PathBuf },
+    /// `gsub cow accept` moved `copy` over `path`
+    Accepted { path: PathBuf, copy: PathBuf },
+    Discarded { path: PathBuf },
+    Diffed { path: PathBuf, diff: String },
+    RestoreFailed {
+        path: PathBuf,
+        #[serde(serialize_with = "error_kind")]
+        kind: io::ErrorKind,
+    },
+    Found { path: PathBuf, lines: Vec<SearchLine> },
+    Counted { path: PathBuf, count: usize },
+    FileMatched { path: PathBuf },
+    Extracted { path: PathBuf, matches: Vec<Extraction> },
+    Included { path: PathBuf },
+    Excluded { path: PathBuf, reason: ExcludeReason },
+    /// `path` is the same file as `first`, which was already visited
+    Duplicate { path: PathBuf, first: PathBuf },
+    WalkFailed { message: String },
+    /// A `--copy-on-write` manifest, or a line of it, that couldn't be read and was left out
+    InvalidManifest { path: PathBuf, line: Option<usize>, message: String },
+    Summary { changed: usize, replacements: usize, unchanged: usize, skipped: usize, failed: usize },
+}
+
+/// Why the walker passed over a file or directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExcludeReason {
+    Except,
+    ExceptPath,
+    Exclude,
+    NotAFile,
+    /// A copy gsub made, or its manifest
+    GsubOutput,
+    /// A symlink we aren't following
+    Symlink,
+    /// A symlink out of the files/directories given, with `--outside-symlinks skip`
+    OutsideRoots,
+    /// A version control directory, or something matching `--protect`
+    Protected,
+}
+
+/// Why a file was left alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    OpenFailed(#[serde(serialize_with = "error_kind")] io::ErrorKind),
+    ReadFailed(#[serde(serialize_with = "error_kind")] io::ErrorKind),
+    LockFailed(#[serde(serialize_with = "error_kind")] io::ErrorKind),
+    InvalidUtf8,
+    ModifiedConcurrently,
+    /// `gsub apply` found the file isn't what `gsub plan` saw
+    ChangedSincePlan,
+    /// `gsub apply` found edits that don't match up with the file
+    InvalidPlan,
+    /// `gsub apply-edits` was given edits that overlap, or don't fit in the file
+    InvalidEdits,
+    /// `gsub apply-edits` was given a ripgrep match in a file whose path can't be decoded
+    UndecodablePath,
+    /// `--output-dir` can only mirror files under the files/directories given
+    OutsideMirror,
+    /// `--copy-on-write` found a copy already there, and `--on-collision` is `error`
+    CopyExists,
+    /// `gsub cow accept` found more than one copy of it, and wasn't told which to accept
+    SeveralCopies,
+    /// A symlink out of the files/directories given, with `--outside-symlinks error`
+    LinkOutsideRoots,
+    /// A symlink given as a file, without `--follow-named-symlinks`
+    NamedSymlink,
+    /// Writing it would mean writing outside the files/directories given
+    OutsideRoots,
+    /// It's in a version control directory, or matches `--protect`
+    Protected,
+}
+
+impl SkipReason {
+    pub fn from_read_error(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => SkipReason::InvalidUtf8,
+            kind => SkipReason::ReadFailed(kind),
+        }
+    }
+}
+
+/// How much the user has to care about an event before they see it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Important,
+    Verbose,
+    Trace,
+}
+
+impl GsubEvent {
+    /// Whether a file was (or would have been) changed
+    pub fn is_change(&self) -> bool {
+        use GsubEvent::*;
+        matches!(self, Updated { .. } | WouldUpdate { .. } | Created { .. })
+    }
+
+    /// The file this event is about, if any
+    pub fn path(&self) -> Option<&Path> {
+        use GsubEvent::*;
+        match self {
+            Updated { path, .. } | WouldUpdate { path, .. } | Unchanged { path }
+            | Found { path, .. } | Counted { path, .. } | FileMatched { path }
+            | Extracted { path, .. }
+            | Skipped { path, .. } | WriteFailed { path, .. } | Restored { path }
+            | Accepted { path, .. } | Discarded { path } | Diffed { path, .. }
+            | RestoreFailed { path, .. } | Included { path } | Excluded { path, .. }
+            | Duplicate { path, .. } | InvalidManifest { path, .. } => Some(path),
+            // Sort by the file that was read, not the copy
+            Created { original, .. } | Copied { original, .. } => Some(original),
+            HookFailed { .. } | VerifyFailed { .. } | VerifyNotRun { .. } => None,
+            WalkFailed { .. } | Summary { .. } => None,
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        use GsubEvent::*;
+        match self {
+            Updated { .. } | WouldUpdate { .. } | Created { .. } => Level::Important,
+            Restored { .. } | Summary { .. } => Level::Important,
+            Accepted { .. } | Discarded { .. } | Diffed { .. } => Level::Important,
+            Found { .. } | Counted { .. } | FileMatched { .. } => Level::Important,
+            Extracted { .. } => Level::Important,
+            Skipped { reason: SkipReason::LockFailed(_), .. } => Level::Error,
+            Skipped { reason: SkipReason::ModifiedConcurrently, .. } => Level::Error,
+            Skipped { reason: SkipReason::ChangedSincePlan, .. } => Level::Error,
+            Skipped { reason: SkipReason::InvalidPlan, .. } => Level::Error,
+            Skipped { reason: SkipReason::InvalidEdits, .. } => Level::Error,
+            Skipped { reason: SkipReason::UndecodablePath, .. } => Level::Error,
+            Skipped { reason: SkipReason::CopyExists, .. } => Level::Error,
+            Skipped { reason: SkipReason::SeveralCopies, .. } => Level::Error,
+            Skipped { reason: SkipReason::LinkOutsideRoots, .. } => Level::Error,
+            Skipped { reason: SkipReason::OutsideRoots, .. } => Level::Error,
+            Skipped { reason: SkipReason::Protected, .. } => Level::Error,
+            Skipped { .. } | Duplicate { .. } | WalkFailed { .. } => Level::Verbose,
+            WriteFailed { .. } | HookFailed { .. } | VerifyFailed { .. } => Level::Error,
+            VerifyNotRun { .. } | InvalidManifest { .. } => Level::Error,
+            RestoreFailed { .. } => Level::Error,
+            Unchanged { .. } | Copied { .. } | Included { .. } | Excluded { .. } => Level::Trace,
+        }
+    }
+}
+
+fn error_kind<S: Serializer>(kind: &io::ErrorKind, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(&format_args!("{:?}", kind))
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::OpenFailed(k) => write!(f, "it couldn't be opened ({})", k),
+            SkipReason::ReadFailed(k) => write!(f, "it couldn't be read ({})", k),
+            SkipReason::LockFailed(io::ErrorKind::WouldBlock) => {
+                write!(f, "it is locked by another process")
+            },
+            SkipReason::LockFailed(k) => write!(f, "it couldn't be locked ({})", k),
+            SkipReason::InvalidUtf8 => write!(f, "it isn't valid UTF-8"),
+            SkipReason::ModifiedConcurrently => write!(f, "it was modified by another process"),
+            SkipReason::ChangedSincePlan => write!(f, "it changed since the plan was made"),
+            SkipReason::InvalidPlan => write!(f, "its planned edits don't line up with it"),
+            SkipReason::InvalidEdits => write!(f, "its edits overlap or don't fit in it"),
+            SkipReason::UndecodablePath => write!(f, "ripgrep's name for it couldn't be decoded"),
+            SkipReason::OutsideMirror => write!(f, "it's outside the directory being mirrored"),
+            SkipReason::CopyExists => write!(f, "a copy of it already exists"),
+            SkipReason::SeveralCopies => {
+                write!(f, "it has more than one copy (name the one to accept)")
+            },
+            SkipReason::LinkOutsideRoots => {
+                write!(f, "it's a symlink to somewhere outside the files given")
+            },
+            SkipReason::NamedSymlink => {
+                write!(f, "it's a symlink (pass --follow-named-symlinks to edit the file it points to)")
+            },
+            SkipReason::OutsideRoots => write!(f, "it resolves to somewhere outside the files given"),
+            SkipReason::Protected => write!(f, "it resolves to somewhere protected"),
+        }
+    }
+}
+
+fn status_str(status: &Option<i32>) -> String {
+    status.map_or_else(|| "killed by a signal".to_string(), |c| format!("exit status: {}", c))
+}
+
+impl GsubEvent {
+    /// Renders the event as text, styling paths and errors with `p`
+    pub fn paint(&self, p: &Painter) -> String {
+        use GsubEvent::*;
+        let path = |path: &Path| p.path(path, None);
+        match self {
+            Updated { path, line, .. } => format!("Updated {}", p.path(path, Some(*line))),
+            WouldUpdate { path, line, .. } => {
+                format!("Would have updated {}", p.path(path, Some(*line)))
+            },
+            Created { path, line, .. } => format!("Created {}", p.path(path, Some(*line))),
+            Unchanged { path: f } => format!("No changes to {}", path(f)),
+            Copied { path: f, original } => format!("Copied {} to {}", path(original), path(f)),
+            Skipped { path: f, reason } if self.level() == Level::Error => {
+                format!("Skipping {} because {}", path(f), p.error(reason))
+            },
+            Skipped { path: f, reason } => format!("Skipping {} because {}", path(f), reason),
+            WriteFailed { path: f, kind } => {
+                format!("Unable to write {} {}", path(f), p.error(format_args!("({})", kind)))
+            },
+            HookFailed { command, status } => {
+                p.error(format_args!("`{}` failed ({})", command, status_str(status)))
+            },
+            VerifyFailed { command, status } => p.error(format_args!(
+                "`{}` failed ({}), rolling back", command, status_str(status)
+            )),
+            VerifyNotRun { command, kind } => p.error(format_args!(
+                "`{}` couldn't be run ({}), rolling back", command, kind
+            )),
+            Restored { path: f } => format!("Restored {}", path(f)),
+            Accepted { path: f, copy } => format!("Replaced {} with {}", path(f), path(copy)),
+            Discarded { path: f } => format!("Deleted {}", path(f)),
+            Diffed { diff, .. } => diff.trim_end_matches('\n').to_string(),
+            RestoreFailed { path: f, kind } => {
+                format!("Unable to restore {} {}", path(f), p.error(format_args!("({})", kind)))
+            },
+            Found { path: f, lines } => paint_search_lines(p, f, lines),
+            Counted { path: f, count } => format!("{}:{}", path(f), count),
+            FileMatched { path: f } => path(f),
+            Extracted { matches, .. } => {
+                matches.iter().map(|m| m.text.as_str()).collect::<Vec<_>>().join("\n")
+            },
+            Included { path: f } => format!("Visiting {}", path(f)),
+            Excluded { path: f, reason: ExcludeReason::Except } => {
+                format!("Excluding {} because it matches --except", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::ExceptPath } => {
+                format!("Excluding {} because it matches --except-path", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::Exclude } => {
+                format!("Excluding {} because it matches --exclude", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::NotAFile } => {
+                format!("Excluding {} because it isn't a regular file", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::GsubOutput } => {
+                format!("Excluding {} because gsub made it", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::Symlink } => {
+                format!("Excluding {} because it's a symlink", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::OutsideRoots } => {
+                format!("Excluding {} because it links outside the files given", path(f))
+            },
+            Excluded { path: f, reason: ExcludeReason::Protected } => {
+                format!("Excluding {} because it's protected", path(f))
+            },
+            Duplicate { path: f, first } => {
+                format!("Skipping {} because it's the same file as {}", path(f), path(first))
+            },
+            WalkFailed { message } => format!("Unable to walk: {}", message),
+            InvalidManifest { path: f, line: Some(line), message } => format!(
+                "Ignoring line {} of {} {}", line, path(f), p.error(format_args!("({})", message))
+            ),
+            InvalidManifest { path: f, line: None, message } => {
+                format!("Ignoring {} {}", path(f), p.error(format_args!("({})", message)))
+            },
+            Summary { changed, replacements, unchanged, skipped, failed } => format!(
+                "{} changed ({} replacements), {} unchanged, {} skipped, {} failed",
+                changed, replacements, unchanged, skipped, failed,
+            ),
+        }
+    }
+}
+
+/// Formats search results like `grep -n --column -C`
+fn paint_search_lines(p: &Painter, path: &Path, lines: &[SearchLine]) -> String {
+    let mut out = Vec::with_capacity(lines.len());
+    let mut previous: Option<usize> = None;
+    for l in lines {
+        if previous.is_some_and(|prev| prev + 1 < l.line) {
+            out.push("--".to_string());
+        }
+        previous = Some(l.line);
+        let mut text = String::with_capacity(l.text.len());
+        let mut last_end = 0;
+        for m in &l.matches {
+            text.push_str(&l.text[last_end..m.start.max(last_end)]);
+            text.push_str(&p.highlight(&l.text[m.start.max(last_end)..m.end.max(last_end)]));
+            last_end = m.end.max(last_end);
+        }
+        text.push_str(&l.text[last_end..]);
+        out.push(match l.column {
+            Some(col) => format!("{}:{}:{}:{}", p.path(path, Some(l.line)), l.line, col, text),
+            None => format!("{}-{}-{}", p.path(path, Some(l.line)), l.line, text),
+        });
+    }
+    out.join("\n")
+}
+
+impl fmt::Display for GsubEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.paint(&Painter::plain()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_utf8_is_distinguishable_from_other_read_errors() {
+        let bad_utf8 = io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8");
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(SkipReason::from_read_error(&bad_utf8), SkipReason::InvalidUtf8);
+        assert_eq!(
+            SkipReason::from_read_error(&denied),
+            SkipReason::ReadFailed(io::ErrorKind::PermissionDenied)
+        );
+    }
+
+    #[test]
+    fn serializes_events_as_tagged_json() {
+        let event = GsubEvent::Skipped {
+            path: PathBuf::from("gabagool.txt"),
+            reason: SkipReason::OpenFailed(io::ErrorKind::PermissionDenied),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"skipped","path":"gabagool.txt","reason":{"open_failed":"PermissionDenied"}}"#
+        );
+        assert_eq!(event.to_string(), "Skipping gabagool.txt because it couldn't be opened (permission denied)");
+    }
+}
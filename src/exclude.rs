@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::RegexSet;
+use crate::CURRENT_DIR;
+use crate::event::ExcludeReason;
+
+/// Read in every directory walked, like `.gitignore`
+pub static GSUBIGNORE_NAME: &str = ".gsubignore";
+
+/// Excluded with `--except`, `--except-path` and `--exclude`, checked against everything walked
+#[derive(Debug)]
+pub struct Exclusions {
+    cwd: PathBuf,
+    /// Matched against file names
+    names: RegexSet,
+    /// Matched against paths relative to the current directory
+    paths: RegexSet,
+    /// gitignore-style globs, relative to the current directory
+    globs: Gitignore,
+}
+
+impl Exclusions {
+    pub fn new(cwd: &Path, names: &[String], paths: &[String], globs: &[String]) -> Result<Self, String> {
+        let mut builder = GitignoreBuilder::new(cwd);
+        for glob in globs {
+            builder.add_line(None, glob).map_err(|e| e.to_string())?;
+        }
+        Ok(Self {
+            cwd: cwd.to_path_buf(),
+            names: RegexSet::new(names).map_err(|e| e.to_string())?,
+            paths: RegexSet::new(paths).map_err(|e| e.to_string())?,
+            globs: builder.build().map_err(|e| e.to_string())?,
+        })
+    }
+
+    pub fn check(&self, path: &Path, is_dir: bool) -> Option<ExcludeReason> {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        if self.names.is_match(&name.to_string_lossy()) {
+            return Some(ExcludeReason::Except);
+        }
+        // Paths outside the current directory have nothing to be relative to
+        let relative = path.strip_prefix(CURRENT_DIR)
+            .or_else(|_| path.strip_prefix(&self.cwd))
+            .unwrap_or(path);
+        if relative.has_root() {
+            return None;
+        }
+        if self.paths.is_match(&relative.to_string_lossy()) {
+            return Some(ExcludeReason::ExceptPath);
+        }
+        if self.globs.matched_path_or_any_parents(relative, is_dir).is_ignore() {
+            return Some(ExcludeReason::Exclude);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_by_name_path_and_glob() {
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let exclusions = Exclusions::new(
+            Path::new("/repo"),
+            &strings(&["^target$"]),
+            &strings(&["^src/generated(/|$)"]),
+            &strings(&["/docs/*.md", "!/docs/README.md", "*.min.js"]),
+        ).unwrap();
+        let check = |p| exclusions.check(Path::new(p), false);
+        assert_eq!(check("./target"), Some(ExcludeReason::Except));
+        assert_eq!(check("./src/generated/a.rs"), Some(ExcludeReason::ExceptPath));
+        assert_eq!(check("/repo/src/generated"), Some(ExcludeReason::ExceptPath));
+        assert_eq!(check("lib/generated/a.rs"), None);
+        assert_eq!(check("docs/guide.md"), Some(ExcludeReason::Exclude));
+        assert_eq!(check("docs/README.md"), None);
+        assert_eq!(check("web/app.min.js"), Some(ExcludeReason::Exclude));
+    }
+}
@@ -1,17 +1,46 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::borrow::Cow;
-use std::fs::{File, OpenOptions};
-use std::io::{Result, Read, Write, Seek, SeekFrom};
+use std::time::SystemTime;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Result, Read, Write, Seek, SeekFrom};
 use ignore::{self, DirEntry};
+use crate::metadata::restore_times;
 
 pub struct FileData {
     file: File,
     estimated_size: u64,
-    dir_entry: DirEntry,
+    path: PathBuf,
+    metadata: Metadata,
+    fingerprint: Fingerprint,
+}
+
+/// Snapshot of a file's size, mtime and identity, used to notice when some other process
+/// modifies (or replaces) a file between us reading it and writing it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    len: u64,
+    modified: Option<SystemTime>,
+    dev: u64,
+    ino: u64,
+}
+
+impl Fingerprint {
+    #[cfg(unix)]
+    pub fn of(md: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self { len: md.len(), modified: md.modified().ok(), dev: md.dev(), ino: md.ino() }
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(md: &Metadata) -> Self {
+        Self { len: md.len(), modified: md.modified().ok(), dev: 0, ino: 0 }
+    }
 }
 
 pub trait OpenFileData {
     fn open_fd(&self, entry: DirEntry) -> Result<FileData>;
+    /// Like `open_fd()`, for files we were told about instead of walking to
+    fn open_path(&self, path: &Path) -> Result<FileData>;
 }
 
 impl OpenFileData for OpenOptions {
@@ -21,13 +50,51 @@ impl OpenFileData for OpenOptions {
             .map(|md| md.len())
             .unwrap_or(0);
         let file = self.open(dir_entry.path())?;
-        Ok(FileData { file, estimated_size, dir_entry })
+        let metadata = file.metadata()?;
+        let fingerprint = Fingerprint::of(&metadata);
+        Ok(FileData { file, estimated_size, path: dir_entry.into_path(), metadata, fingerprint })
+    }
+
+    fn open_path(&self, path: &Path) -> Result<FileData> {
+        let file = self.open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+        }
+        let fingerprint = Fingerprint::of(&metadata);
+        let estimated_size = metadata.len();
+        Ok(FileData { file, estimated_size, path: path.to_path_buf(), metadata, fingerprint })
     }
 }
 
 impl FileData {
-    pub fn path(&self) -> &Path { self.dir_entry.path() }
+    pub fn path(&self) -> &Path { &self.path }
     pub fn path_str(&self) -> Cow<'_, str> { self.path().to_string_lossy() }
+    /// As it was when we opened the file
+    pub fn metadata(&self) -> &Metadata { &self.metadata }
+
+    /// Puts the access and modification times back to what they were when we opened the file
+    pub fn restore_times(&self) -> Result<()> {
+        restore_times(&self.file, &self.metadata)
+    }
+
+    /// Returns false if the file at our path no longer looks like the one we opened.
+    pub fn unmodified_since_open(&self) -> Result<bool> {
+        let current = Fingerprint::of(&fs::metadata(self.path())?);
+        Ok(current == self.fingerprint)
+    }
+
+    /// Takes an exclusive advisory lock on the file, failing with `WouldBlock` if another
+    /// process already holds one. The lock is released when the file is closed.
+    #[cfg(unix)]
+    pub fn lock(&self) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        let rc = unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if rc == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+    }
+
+    #[cfg(not(unix))]
+    pub fn lock(&self) -> Result<()> { Ok(()) }
 }
 
 // It's kind of tedious to define all of these traits, but it does let us unit test really well
@@ -41,11 +108,38 @@ impl ByteSized for FileData {
     fn byte_size(&self) -> usize { self.estimated_size as usize }
 }
 
+/// Standard input, for using gsub as a filter
+pub struct StdinData(io::Stdin);
+
+impl StdinData {
+    pub fn new() -> Self { StdinData(io::stdin()) }
+}
+
+impl Default for StdinData {
+    fn default() -> Self { Self::new() }
+}
+
+impl ByteSized for StdinData {
+    fn byte_size(&self) -> usize { 0 }
+}
+
+impl Read for StdinData {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> { self.0.read(buf) }
+}
+
+impl SizedReader for StdinData {}
+
 impl Read for FileData {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> { self.file.read(buf) }
 }
 
-pub trait SizedReader: ByteSized + Read {}
+pub trait SizedReader: ByteSized + Read {
+    fn read_all(&mut self) -> Result<String> {
+        let mut buffer = String::with_capacity(self.byte_size());
+        self.read_to_string(&mut buffer)?;
+        Ok(buffer)
+    }
+}
 impl SizedReader for FileData {}
 
 pub trait Truncable {
@@ -118,6 +212,7 @@ pub mod tests {
     impl OverWrite for MockFileData {}
 
     #[test]
+    #[allow(clippy::unused_io_amount)]
     fn overwrites_the_entire_file_with_larger_diffs() {
         let mut file = MockFileData::new("oat milk is tasty");
         file.write(b"almond").unwrap();
@@ -131,4 +226,16 @@ pub mod tests {
         file.overwrite(b"soy milk is the bomb").unwrap();
         assert_eq!(file.utf8_contents(), "soy milk is the bomb");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn fingerprints_differ_after_a_file_is_modified() {
+        let path = "test-files/fingerprints-differ";
+        fs::write(path, "oat milk").unwrap();
+        let before = Fingerprint::of(&fs::metadata(path).unwrap());
+        assert_eq!(before, Fingerprint::of(&fs::metadata(path).unwrap()));
+        fs::write(path, "almond milk").unwrap();
+        let after = Fingerprint::of(&fs::metadata(path).unwrap());
+        fs::remove_file(path).unwrap();
+        assert_ne!(before, after);
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+
+/// Calls `f` with every path in `reader`, where each path ends with `separator`. Handles the
+/// output of `git ls-files -z`, `rg -l --null`, `fd -0` and friends (or their newline versions).
+pub fn for_each_path(
+    reader: impl BufRead,
+    separator: u8,
+    mut f: impl FnMut(PathBuf),
+) -> io::Result<()>
+{
+    for chunk in reader.split(separator) {
+        let mut bytes = chunk?;
+        if separator == b'\n' && bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        if !bytes.is_empty() {
+            f(path_from_bytes(bytes));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(input: &[u8], separator: u8) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for_each_path(input, separator, |p| paths.push(p)).unwrap();
+        paths
+    }
+
+    #[test]
+    fn reads_newline_separated_paths() {
+        assert_eq!(
+            paths(b"src/main.rs\r\nsrc/lib.rs\n\nREADME.md", b'\n'),
+            vec![PathBuf::from("src/main.rs"), PathBuf::from("src/lib.rs"), PathBuf::from("README.md")]
+        );
+    }
+
+    #[test]
+    fn reads_nul_separated_paths_with_newlines_in_them() {
+        assert_eq!(
+            paths(b"weird\nname.txt\0src/lib.rs\0", b'\0'),
+            vec![PathBuf::from("weird\nname.txt"), PathBuf::from("src/lib.rs")]
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use crate::opts::Opts;
+use crate::replacer::Replacer;
+use crate::presenter::Presenter;
+use crate::search::search_contents;
+use crate::file_data::{SizedReader, StdinData};
+
+/// How stdin shows up in output, e.g. for `--search`
+pub static STDIN_NAME: &str = "<stdin>";
+
+/// Reads stdin and writes it to stdout with replacements made, like `sed`. When only reading
+/// (`--search`, `--only-output`, etc.) stdin is treated like any other file.
+pub fn filter_stdin(replacer: &Replacer, opts: &Opts, presenter: &Presenter) -> io::Result<()> {
+    let contents = StdinData::new().read_all()?;
+    if opts.only_reading() {
+        presenter.wax(search_contents(PathBuf::from(STDIN_NAME), &contents, replacer, opts));
+        return Ok(());
+    }
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(replacer.replace_all(&contents).as_bytes())?;
+    stdout.flush()
+}
@@ -1,59 +1,171 @@
-use std::{io, fs};
+use std::{fs, io};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::ffi::{OsStr, OsString};
+use std::ffi::OsString;
+use std::str::FromStr;
+use ignore::DirEntry;
 use crate::opts::Opts;
 use crate::replacer::Replacer;
-use crate::presenter::{Msg, ToMsg};
-use crate::file_data::{FileData, OverWrite};
+use crate::event::{GsubEvent, SkipReason};
+use crate::changes::ChangeLog;
+use crate::proposals::Proposals;
+use crate::metadata::copy_metadata;
+use crate::file_data::{OpenFileData, OverWrite};
 
-pub static GSUB_EXT_PATTERN: &str = r"((.*)(\.)gsub)$";
-static GSUB_EXT_NAME: &str = "gsub";
-static GSUB_EXT: &str = ".gsub";
+pub static GSUB_EXT_NAME: &str = "gsub";
+
+/// What `--copy-on-write` does when the copy it wants to make already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// Skip the file, leaving the existing copy alone
+    Error,
+    Overwrite,
+    /// Number the new copy, e.g. `main.rs.1.gsub`
+    Number,
+}
+
+impl Collision {
+    pub const VARIANTS: &'static [&'static str] = &["error", "overwrite", "number"];
+}
+
+impl FromStr for Collision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Collision::Error),
+            "overwrite" => Ok(Collision::Overwrite),
+            "number" => Ok(Collision::Number),
+            _ => Err(format!("unknown collision policy '{}'", s)),
+        }
+    }
+}
 
 pub fn gsub(
-    fd_result: io::Result<FileData>,
+    entry: DirEntry,
+    opener: &impl OpenFileData,
     replacer: &Replacer,
     opts: &Opts,
-) -> Result<Option<Msg<String>>, Msg<String>>
+    changes: &ChangeLog,
+    proposals: Option<&dyn Proposals>,
+) -> Result<GsubEvent, GsubEvent>
 {
-    let mut fd = fd_result.map_err(ToMsg::verbose)?;
-    let replacement = replacer.replace(&mut fd).map_err(|e| {
-        format!("Skipping {} because {}", fd.path_str(), e).verbose()
-    })?;
-    let new_contents = match replacement {
-        Some(s) => s,
-        None => return Ok(None),
+    let skipped = |path: &Path, reason| GsubEvent::Skipped { path: path.to_path_buf(), reason };
+    let path = entry.path().to_path_buf();
+    let mut fd = opener.open_fd(entry)
+        .map_err(|e| skipped(&path, SkipReason::OpenFailed(e.kind())))?;
+    if let Some(guard) = &opts.guard {
+        guard.check_opened(&path, fd.metadata()).map_err(|reason| skipped(&path, reason))?;
+    }
+    if opts.lock {
+        fd.lock().map_err(|e| skipped(&path, SkipReason::LockFailed(e.kind())))?;
+    }
+    let replacement = match replacer.replace(&mut fd) {
+        Ok(Some(r)) => r,
+        Ok(None) => return copy_unchanged(path, opts),
+        // Files that aren't text still belong in a full copy
+        Err(e) => match SkipReason::from_read_error(&e) {
+            SkipReason::InvalidUtf8 if opts.copy_unchanged => return copy_unchanged(path, opts),
+            reason => return Err(skipped(&path, reason)),
+        },
     };
-    let success_msg = if opts.dry_run {
-        format!("Would have updated {}", fd.path_str()).important()
+    if !opts.dry_running() && !fd.unmodified_since_open().unwrap_or(false) {
+        return Err(skipped(&path, SkipReason::ModifiedConcurrently));
+    }
+    let (replacements, line) = (replacement.replacements, replacement.first_line);
+    let event = if let Some(proposals) = proposals {
+        proposals.propose(&path, &replacement, replacer);
+        GsubEvent::WouldUpdate { path, replacements, line }
+    } else if opts.dry_run {
+        GsubEvent::WouldUpdate { path, replacements, line }
+    } else if let Some(mirror) = &opts.mirror {
+        let to = mirror.path_for(&path).ok_or_else(|| skipped(&path, SkipReason::OutsideMirror))?;
+        mirror.write(&to, replacement.replaced.as_bytes())
+            .and_then(|_| copy_metadata(&path, fd.metadata(), &to, opts.preserve_mtime))
+            .map_err(|e| GsubEvent::WriteFailed { path: to.clone(), kind: e.kind() })?;
+        changes.record(&to, replacement.original);
+        GsubEvent::Created { path: to, original: path, replacements, line }
     } else if opts.copy_on_write {
-        let new_file_name = add_gsub_ext(fd.path());
-        fs::write(&new_file_name, new_contents).map_err(ToMsg::important)?;
-        format!("Created {}", new_file_name.to_string_lossy()).important()
+        let suffix = opts.cow_suffix();
+        let new_file_name = write_copy(&path, suffix, replacement.replaced.as_bytes(), opts.on_collision)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => skipped(&path, SkipReason::CopyExists),
+                kind => GsubEvent::WriteFailed { path: cow_path(&path, suffix, 0), kind },
+            })?;
+        copy_metadata(&path, fd.metadata(), &new_file_name, opts.preserve_mtime)
+            .map_err(|e| GsubEvent::WriteFailed { path: new_file_name.clone(), kind: e.kind() })?;
+        changes.record_copy(&new_file_name, &path, replacement.original);
+        GsubEvent::Created { path: new_file_name, original: path, replacements, line }
     } else {
-        fd.overwrite(&new_contents.as_bytes()).map_err(ToMsg::important)?;
-        format!("Updated {}", fd.path_str()).important()
+        fd.overwrite(replacement.replaced.as_bytes())
+            .and_then(|_| if opts.preserve_mtime { fd.restore_times() } else { Ok(()) })
+            .map_err(|e| GsubEvent::WriteFailed { path: path.clone(), kind: e.kind() })?;
+        changes.record(&path, replacement.original);
+        GsubEvent::Updated { path, replacements, line }
+    };
+    Ok(event)
+}
+
+/// Copies a file we didn't change into `--output-dir`, if we're making a full copy
+fn copy_unchanged(path: PathBuf, opts: &Opts) -> Result<GsubEvent, GsubEvent> {
+    let to = match (&opts.mirror, opts.copy_unchanged) {
+        (Some(mirror), true) => mirror.path_for(&path).map(|to| (mirror, to)),
+        _ => None,
     };
-    Ok(Some(success_msg))
+    match to {
+        Some((mirror, to)) => {
+            mirror.copy(&path, &to)
+                .and_then(|_| copy_metadata(&path, &fs::metadata(&path)?, &to, opts.preserve_mtime))
+                .map_err(|e| GsubEvent::WriteFailed { path: to.clone(), kind: e.kind() })?;
+            Ok(GsubEvent::Copied { path: to, original: path })
+        },
+        None => Ok(GsubEvent::Unchanged { path }),
+    }
+}
+
+/// Writes a copy of `path` with `contents`, returning where it went
+fn write_copy(path: &Path, suffix: &str, contents: &[u8], on_collision: Collision) -> io::Result<PathBuf> {
+    if on_collision == Collision::Overwrite {
+        let copy = cow_path(path, suffix, 0);
+        fs::write(&copy, contents)?;
+        return Ok(copy);
+    }
+    let mut n = 0;
+    loop {
+        let copy = cow_path(path, suffix, n);
+        // create_new, so nothing can sneak in between us checking for a copy and writing one
+        match OpenOptions::new().write(true).create_new(true).open(&copy) {
+            Ok(mut file) => {
+                file.write_all(contents)?;
+                return Ok(copy);
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && on_collision == Collision::Number => {
+                n += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// `path` with `.{suffix}` added to the end, numbered first if `n` isn't 0
+pub fn cow_path(path: &Path, suffix: &str, n: usize) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    if n > 0 {
+        name.push(format!(".{}", n));
+    }
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
 }
 
-fn add_gsub_ext(path: impl AsRef<Path>) -> PathBuf {
-    let mut file_name = path.as_ref().to_path_buf();
-    let new_ext = file_name
-        .extension()
-        .map(OsString::from)
-        .map(|mut ext| {
-            ext.push(OsStr::new(GSUB_EXT));
-            ext
-        }).unwrap_or_else(|| OsString::from(GSUB_EXT_NAME));
-    file_name.set_extension(new_ext);
-    file_name
+pub fn add_gsub_ext(path: impl AsRef<Path>) -> PathBuf {
+    cow_path(path.as_ref(), GSUB_EXT_NAME, 0)
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use regex::RegexSet;
 
     #[test]
     fn adds_expected_gsub_ext_to_a_file_with_no_ext() {
@@ -70,15 +182,8 @@ pub mod tests {
     }
 
     #[test]
-    fn gsub_ext_pattern_matches_against_expected_file_paths() {
-        let rs = RegexSet::new(&[GSUB_EXT_PATTERN]).expect("didn't compile");
-
-        assert!(rs.is_match("somefile.gsub"));
-        assert!(rs.is_match(".some-other-file.txt.gsub"));
-        assert!(rs.is_match("./some_dir/some_file.gsub"));
-
-        assert!(!rs.is_match("main.rs"));
-        assert!(!rs.is_match("gsub.txt"));
-        assert!(!rs.is_match("main.gsub.rs"));
+    fn numbers_copies() {
+        let p = PathBuf::from("dir/gabagool.txt");
+        assert_eq!(cow_path(&p, "bak", 2).to_string_lossy(), "dir/gabagool.txt.2.bak")
     }
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use crate::CURRENT_DIR;
+use crate::event::SkipReason;
+use crate::metadata::file_id;
+
+/// Version control directories, which are never edited, even with `--hidden`
+pub static VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Keeps edits inside the files/directories given, and away from anything protected
+#[derive(Debug)]
+pub struct Guard {
+    /// The roots as given, and where they really are
+    given: Vec<PathBuf>,
+    roots: Vec<PathBuf>,
+    confined: bool,
+    /// Globs without a `/`, matched against each part of a path
+    names: GlobSet,
+    /// Globs with a `/`, matched against a path and its parents
+    paths: GlobSet,
+}
+
+impl Guard {
+    /// `protect` are gitignore-style globs: `*.lock` protects lockfiles anywhere, `vendor/**`
+    /// just the top level vendor directory
+    pub fn new(roots: &[PathBuf], protect: &[String]) -> Result<Self, globset::Error> {
+        let given = roots.to_vec();
+        let roots = roots.iter().filter_map(|r| fs::canonicalize(r).ok()).collect();
+        let (mut names, mut paths) = (GlobSetBuilder::new(), GlobSetBuilder::new());
+        for dir in VCS_DIRS {
+            names.add(Glob::new(dir)?);
+        }
+        for glob in protect {
+            let glob = glob.trim_start_matches("./");
+            let builder = if glob.contains('/') { &mut paths } else { &mut names };
+            builder.add(GlobBuilder::new(glob.trim_start_matches('/')).literal_separator(true).build()?);
+        }
+        Ok(Self { given, roots, confined: true, names: names.build()?, paths: paths.build()? })
+    }
+
+    /// Lets edits go outside the roots, e.g. for symlinks deliberately followed out of them
+    pub fn confined(mut self, yes: bool) -> Self {
+        self.confined = yes;
+        self
+    }
+
+    /// Whether `path`, as it was walked, is protected. Globs with a `/` are relative to the
+    /// current directory or the root `path` was found under.
+    pub fn protects(&self, path: &Path) -> bool {
+        let path = path.strip_prefix(CURRENT_DIR).unwrap_or(path);
+        let under_root = self.given.iter().filter_map(|r| path.strip_prefix(r).ok());
+        path.components().any(|c| self.names.is_match(c.as_os_str()))
+            || std::iter::once(path).chain(under_root).any(|p| p.ancestors().any(|a| self.paths.is_match(a)))
+    }
+
+    /// Checks `path` is somewhere we may write to, once symlinks and `..` are resolved
+    pub fn check(&self, path: &Path) -> Result<(), SkipReason> {
+        self.resolve(path).map(|_| ())
+    }
+
+    /// Like `check()`, for a file already opened as `path`. Where `path` leads can change
+    /// between opening it and checking it, so this also checks it still leads to what we opened.
+    pub fn check_opened(&self, path: &Path, opened: &Metadata) -> Result<(), SkipReason> {
+        let resolved = self.resolve(path)?;
+        let current = fs::metadata(resolved).map_err(|e| SkipReason::OpenFailed(e.kind()))?;
+        if file_id(&current) != file_id(opened) {
+            return Err(SkipReason::ModifiedConcurrently);
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, path: &Path) -> Result<PathBuf, SkipReason> {
+        let resolved = fs::canonicalize(path).map_err(|e| SkipReason::OpenFailed(e.kind()))?;
+        if self.confined && !self.roots.iter().any(|r| resolved.starts_with(r)) {
+            return Err(SkipReason::OutsideRoots);
+        }
+        // Relative to its root, so globs like `vendor/**` still apply
+        let relative = self.roots.iter()
+            .find_map(|r| resolved.strip_prefix(r).ok())
+            .unwrap_or(&resolved);
+        if self.protects(path) || self.protects(relative) {
+            return Err(SkipReason::Protected);
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protects_vcs_directories_and_globs() {
+        let protect = ["*.lock".to_string(), "vendor/**".to_string()];
+        let guard = Guard::new(&[PathBuf::from("sub")], &protect).unwrap();
+        assert!(guard.protects(Path::new("./.git/config")));
+        assert!(guard.protects(Path::new("sub/.hg")));
+        assert!(guard.protects(Path::new("./Cargo.lock")));
+        assert!(guard.protects(Path::new("sub/yarn.lock")));
+        assert!(guard.protects(Path::new("vendor/lib/a.rs")));
+        assert!(guard.protects(Path::new("sub/vendor/a.rs")));
+        assert!(!guard.protects(Path::new("src/vendor/a.rs")));
+        assert!(!guard.protects(Path::new("src/.gitignore")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_files_swapped_after_opening() {
+        use std::os::unix::fs::symlink;
+        fs::create_dir_all("test-files/guard-swapped").unwrap();
+        fs::write("test-files/guard-swapped/milk.txt", "oat milk").unwrap();
+        fs::write("test-files/guard-swapped/other.txt", "soy milk").unwrap();
+        let guard = Guard::new(&[PathBuf::from("test-files/guard-swapped")], &[]).unwrap();
+        let path = Path::new("test-files/guard-swapped/milk.txt");
+
+        let opened = fs::File::open(path).unwrap().metadata().unwrap();
+        let unchanged = guard.check_opened(path, &opened);
+        fs::remove_file(path).unwrap();
+        symlink("other.txt", path).unwrap();
+        let swapped = guard.check_opened(path, &opened);
+        fs::remove_dir_all("test-files/guard-swapped").unwrap();
+
+        assert_eq!(unchanged, Ok(()));
+        assert_eq!(swapped, Err(SkipReason::ModifiedConcurrently));
+    }
+}
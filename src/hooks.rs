@@ -0,0 +1,99 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+
+/// Placeholder in `--exec-changed` commands for the list of changed files
+pub static FILES_PLACEHOLDER: &str = "{}";
+
+/// Roughly how many bytes of file names we pass to a single invocation. Well under the
+/// ARG_MAX of any platform we care about.
+static MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// Builds a command that runs `cmd` through the platform's shell
+pub fn shell(cmd: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(cmd);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(cmd);
+        c
+    }
+}
+
+/// Runs `cmd` on `paths`, like `find -exec cmd {} +`. Every `{}` in `cmd` is replaced with
+/// as many paths as fit in one invocation (or they're appended, if there's no `{}`).
+pub fn exec_batched(cmd: &str, paths: &[PathBuf]) -> io::Result<Vec<ExitStatus>> {
+    batches(paths)
+        .into_iter()
+        .map(|batch| batch_command(cmd, batch).status())
+        .collect()
+}
+
+fn batches(paths: &[PathBuf]) -> Vec<&[PathBuf]> {
+    let mut batches = Vec::new();
+    let (mut start, mut bytes) = (0, 0);
+    for (i, p) in paths.iter().enumerate() {
+        let len = p.as_os_str().len() + 1;
+        if i > start && bytes + len > MAX_BATCH_BYTES {
+            batches.push(&paths[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += len;
+    }
+    if start < paths.len() {
+        batches.push(&paths[start..]);
+    }
+    batches
+}
+
+#[cfg(not(windows))]
+fn batch_command(cmd: &str, batch: &[PathBuf]) -> Command {
+    // Hand the paths to `sh` as positional parameters, so they never need quoting
+    let script = if cmd.contains(FILES_PLACEHOLDER) {
+        cmd.replace(FILES_PLACEHOLDER, "\"$@\"")
+    } else {
+        format!("{} \"$@\"", cmd)
+    };
+    let mut c = shell(&script);
+    c.arg("sh").args(batch);
+    c
+}
+
+#[cfg(windows)]
+fn batch_command(cmd: &str, batch: &[PathBuf]) -> Command {
+    let files = batch.iter()
+        .map(|p| format!("\"{}\"", p.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let script = if cmd.contains(FILES_PLACEHOLDER) {
+        cmd.replace(FILES_PLACEHOLDER, &files)
+    } else {
+        format!("{} {}", cmd, files)
+    };
+    shell(&script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_paths_by_total_length() {
+        let long_name = "a".repeat(MAX_BATCH_BYTES / 2);
+        let paths = vec![PathBuf::from(&long_name); 5];
+        let sizes = batches(&paths).iter().map(|b| b.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![1, 1, 1, 1, 1]);
+
+        let paths = vec![PathBuf::from("short"); 5];
+        let sizes = batches(&paths).iter().map(|b| b.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![5]);
+    }
+
+    #[test]
+    fn no_batches_without_paths() {
+        assert!(batches(&[]).is_empty());
+    }
+}
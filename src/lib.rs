@@ -1,7 +1,30 @@
 pub static CURRENT_DIR: &str = ".";
+pub static STDIN_PATH: &str = "-";
 
 pub mod opts;
 pub mod gsub;
 pub mod replacer;
 pub mod file_data;
-pub mod presenter;
\ No newline at end of file
+pub mod presenter;
+pub mod event;
+pub mod progress;
+pub mod color;
+pub mod search;
+pub mod filter;
+pub mod exclude;
+pub mod visited;
+pub mod symlinks;
+pub mod guard;
+pub mod file_list;
+pub mod proposals;
+pub mod patch;
+pub mod plan;
+pub mod edits;
+pub mod editor;
+pub mod cow;
+pub mod paths;
+pub mod mirror;
+pub mod manifest;
+pub mod metadata;
+pub mod changes;
+pub mod hooks;
\ No newline at end of file
@@ -1,60 +1,473 @@
-use std::io;
+use std::env;
+use std::collections::{BTreeMap, BTreeSet};
+use std::thread;
+use std::sync::{mpsc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{self, BufRead, Write};
 use std::error::Error;
-use regex::RegexSet;
-use ignore::{self, DirEntry, WalkState};
+use structopt::StructOpt;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use ignore::{self, DirEntry, WalkBuilder, WalkState};
 use gsub::gsub::gsub;
-use gsub::opts::Opts;
-use gsub::file_data::OpenFileData;
+use gsub::search::search;
+use gsub::filter::filter_stdin;
+use gsub::progress::Progress;
+use gsub::file_list::for_each_path;
+use gsub::opts::{ApplyEditsOpts, ApplyOpts, CowAction, CowOpts, Opts};
+use gsub::cow::{self, find_copies};
+use gsub::edits::{apply_edits, ExternalEdit};
+use gsub::plan::apply_file;
+use gsub::event::{GsubEvent, ExcludeReason, SkipReason};
+use gsub::presenter::Presenter;
+use gsub::hooks;
+use gsub::manifest::{Manifest, MANIFEST_NAME};
+use gsub::visited::Visited;
+use gsub::symlinks::{LinkAction, Symlinks};
+use gsub::guard::Guard;
+use gsub::exclude::Exclusions;
 
 fn io_err<E>(e: E) -> io::Error
     where E: Into<Box<dyn Error + Send + Sync>>,
 {
-    io::Error::new(io::ErrorKind::Other, e)
+    io::Error::other(e)
 }
 
 fn get_allowed_file_entry(
     entry_result: Result<DirEntry, ignore::Error>,
-    blacklist: &RegexSet,
+    exclusions: &Exclusions,
+    manifest: Option<&Manifest>,
+    visited: &Visited,
+    symlinks: &Symlinks,
+    guard: Option<&Guard>,
+    report: &dyn Fn(GsubEvent),
 ) -> Result<DirEntry, WalkState>
 {
-    let entry = entry_result.map_err(|_| WalkState::Continue)?;
+    let entry = entry_result.map_err(|e| {
+        report(GsubEvent::WalkFailed { message: e.to_string() });
+        WalkState::Continue
+    })?;
     let file_type = entry.file_type().ok_or(WalkState::Continue)?;
-    debug_assert!(
-        !file_type.is_symlink(),
-        "walk_builder() should've been configured to protect against symlinks"
-    );
 
-    let blacklisted = blacklist.is_match(&entry.file_name().to_string_lossy());
+    let excluded = |reason| GsubEvent::Excluded { path: entry.path().to_path_buf(), reason };
+    // A directory we won't walk into is skipped whole
+    let skip = if file_type.is_dir() { WalkState::Skip } else { WalkState::Continue };
+    if guard.is_some_and(|g| g.protects(entry.path())) {
+        report(excluded(ExcludeReason::Protected));
+        return Err(skip);
+    }
+    match symlinks.check(&entry) {
+        LinkAction::Visit => {},
+        LinkAction::Ignore => {
+            report(excluded(ExcludeReason::Symlink));
+            return Err(skip);
+        },
+        // Someone asked for this one by name, so they'll want to know why nothing happened
+        LinkAction::IgnoreNamed => {
+            let path = entry.path().to_path_buf();
+            report(GsubEvent::Skipped { path, reason: SkipReason::NamedSymlink });
+            return Err(skip);
+        },
+        LinkAction::Skip => {
+            report(excluded(ExcludeReason::OutsideRoots));
+            return Err(skip);
+        },
+        LinkAction::Refuse => {
+            let path = entry.path().to_path_buf();
+            report(GsubEvent::Skipped { path, reason: SkipReason::LinkOutsideRoots });
+            return Err(skip);
+        },
+    }
+    let exclusion = exclusions.check(entry.path(), file_type.is_dir());
     let is_file = file_type.is_file();
-    match (is_file, blacklisted) {
-        (true, false) => Ok(entry),
-        (true, true) => Err(WalkState::Continue),
-        (false, false) => Err(WalkState::Continue),
-        (false, true) => Err(WalkState::Skip),
+    let is_manifest = entry.file_name() == MANIFEST_NAME;
+    if is_file && (is_manifest || manifest.is_some_and(|m| m.made(entry.path()))) {
+        report(excluded(ExcludeReason::GsubOutput));
+        return Err(WalkState::Continue);
+    }
+    match (is_file, exclusion) {
+        (true, None) => {
+            let path = entry.path().to_path_buf();
+            if let Some(first) = entry.metadata().ok().and_then(|md| visited.visit(&path, &md)) {
+                report(GsubEvent::Duplicate { path, first });
+                return Err(WalkState::Continue);
+            }
+            report(GsubEvent::Included { path });
+            Ok(entry)
+        },
+        (true, Some(reason)) => {
+            report(excluded(reason));
+            Err(WalkState::Continue)
+        },
+        (false, None) => {
+            if !file_type.is_dir() {
+                report(excluded(ExcludeReason::NotAFile));
+            }
+            Err(WalkState::Continue)
+        },
+        (false, Some(reason)) => {
+            report(excluded(reason));
+            Err(WalkState::Skip)
+        },
     }
 }
 
-fn main() -> std::io::Result<()> {
-    let opts = Opts::parse().map_err(io_err)?;
-    let replacer = opts.replacer().map_err(io_err)?;
-    let blacklist = opts.dir_entry_blacklist().map_err(io_err)?;
+/// Counts the files the real walk will visit, for the progress line, giving up early if the
+/// real walk beats us to it. Everything the real walk leaves out is left out here too, quietly.
+fn count_files(
+    opts: &Opts,
+    exclusions: &Exclusions,
+    manifest: Option<&Manifest>,
+    symlinks: &Symlinks,
+    progress: &Progress,
+) {
+    let visited = Visited::new();
+    walk_sequentially(&opts.walk_builder(), |result| {
+        let allowed = get_allowed_file_entry(
+            result,
+            exclusions,
+            manifest,
+            &visited,
+            symlinks,
+            opts.guard.as_ref(),
+            &|_| {},
+        );
+        match allowed {
+            Ok(_) if progress.count_file() => WalkState::Continue,
+            Ok(_) => WalkState::Quit,
+            Err(walk_state) => walk_state,
+        }
+    });
+    progress.counted_all();
+}
+
+/// Same as `WalkParallel::run`, but on this thread, visiting entries in order
+fn walk_sequentially<F>(wb: &WalkBuilder, visit: F)
+    where F: Fn(Result<DirEntry, ignore::Error>) -> WalkState,
+{
+    let mut skipped_dir: Option<PathBuf> = None;
+    for result in wb.build() {
+        if let (Some(dir), Ok(entry)) = (&skipped_dir, &result) {
+            if entry.path().starts_with(dir) {
+                continue;
+            }
+        }
+        let dir = result.as_ref().ok()
+            .filter(|e| e.file_type().is_some_and(|ft| ft.is_dir()))
+            .map(|e| e.path().to_path_buf());
+        match visit(result) {
+            WalkState::Skip => skipped_dir = dir,
+            WalkState::Quit => break,
+            WalkState::Continue => {},
+        }
+    }
+}
+
+/// How many `--files-from` paths share a `WalkBuilder`. Building one per path is slow for
+/// lists the size of `git ls-files`, and one for the whole list can't start until it's read.
+static FILE_LIST_BATCH: usize = 512;
+
+/// Walks each path from `--files-from` (and any given on the command line) as it's read, in
+/// batches spread across `opts.thread_count()` threads
+fn walk_file_list<F>(opts: &Opts, list: impl BufRead, separator: u8, visit: F) -> io::Result<()>
+    where F: Fn(Result<DirEntry, ignore::Error>) -> WalkState + Sync,
+{
+    let (tx, rx) = mpsc::sync_channel::<Vec<PathBuf>>(opts.thread_count());
+    let rx = Mutex::new(rx);
+    thread::scope(|s| {
+        for _ in 0..opts.thread_count() {
+            s.spawn(|| loop {
+                let next = rx.lock().unwrap().recv();
+                match next {
+                    Ok(roots) => walk_sequentially(&opts.walk_builder_for(&roots), &visit),
+                    Err(_) => return,
+                }
+            });
+        }
+        // Sends only fail once every worker has gone, which can't happen while we hold `tx`
+        let mut batch = opts.files.clone();
+        let read = for_each_path(list, separator, |root| {
+            batch.push(root);
+            if batch.len() >= FILE_LIST_BATCH {
+                let _ = tx.send(std::mem::take(&mut batch));
+            }
+        });
+        if !batch.is_empty() {
+            let _ = tx.send(batch);
+        }
+        drop(tx);
+        read
+    })
+}
+
+/// Subcommands are picked off before the usual options are parsed, so plain
+/// `gsub <pattern> <replacement>` keeps working without one. They only count as the very first
+/// argument, so `gsub -- plan ...` replaces the word itself.
+fn main() -> io::Result<()> {
+    let mut args = env::args_os().collect::<Vec<_>>();
+    match args.get(1).and_then(|arg| arg.to_str()) {
+        Some("plan") => {
+            args.splice(0..2, [OsString::from("gsub plan")]);
+            run(Opts::parse_plan(args).map_err(io_err)?)
+        },
+        Some("apply") => {
+            args.splice(0..2, [OsString::from("gsub apply")]);
+            apply(ApplyOpts::from_iter(args))
+        },
+        Some("apply-edits") => {
+            args.splice(0..2, [OsString::from("gsub apply-edits")]);
+            apply_external_edits(ApplyEditsOpts::from_iter(args))
+        },
+        Some("cow") => {
+            args.splice(0..2, [OsString::from("gsub cow")]);
+            cow(CowOpts::from_iter(args))
+        },
+        _ => run(Opts::parse().map_err(io_err)?),
+    }
+}
+
+/// Refuses to write to `path` if it's protected, as it is or once resolved. Paths that can't be
+/// resolved are left for whatever opens them to report.
+fn unprotected(guard: &Guard, path: &Path) -> Result<(), GsubEvent> {
+    if guard.protects(path) || guard.check(path) == Err(SkipReason::Protected) {
+        return Err(GsubEvent::Skipped { path: path.to_path_buf(), reason: SkipReason::Protected });
+    }
+    Ok(())
+}
+
+/// Presents every result, returning whether any of them failed
+fn present_all(
+    presenter: &Presenter,
+    results: impl Iterator<Item = Result<GsubEvent, GsubEvent>>,
+) -> bool
+{
+    let mut failed = false;
+    for result in results {
+        match result {
+            Ok(event) => presenter.wax(event),
+            Err(event) => {
+                failed = true;
+                presenter.wax(event)
+            },
+        }
+    }
+    presenter.finish();
+    failed
+}
+
+fn apply(opts: ApplyOpts) -> io::Result<()> {
+    let presenter = opts.presenter();
     let opener = opts.open_opts();
+    let guard = opts.guard()?;
+    let plan = opts.read_plan()?;
+    let results = plan.files.iter().map(|file| {
+        unprotected(&guard, &file.path)?;
+        apply_file(file, &opener, opts.lock, opts.dry_run)
+    });
+    if present_all(&presenter, results) {
+        return Err(io_err("some planned edits couldn't be applied"));
+    }
+    Ok(())
+}
+
+fn apply_external_edits(opts: ApplyEditsOpts) -> io::Result<()> {
     let presenter = opts.presenter();
-    let walker = opts.walk_builder().build_parallel();
-
-    walker.run(|| {
-        Box::new(|result| {
-            let entry = match get_allowed_file_entry(result, &blacklist) {
-                Ok(e) => e,
-                Err(walk_state) => return walk_state,
-            };
-            match gsub(opener.open_fd(entry), &replacer, &opts) {
-                Ok(Some(msg)) | Err(msg) => presenter.wax(msg),
-                Ok(None) => {},
-            }
-            WalkState::Continue
-        })
+    let opener = opts.open_opts();
+    let read = opts.read_edits()?;
+    let mut by_path: BTreeMap<PathBuf, Vec<ExternalEdit>> = BTreeMap::new();
+    for edit in read.edits {
+        by_path.entry(edit.path.clone()).or_default().push(edit);
+    }
+    let undecodable = read.undecodable.into_iter()
+        .map(|path| Err(GsubEvent::Skipped { path, reason: SkipReason::UndecodablePath }));
+    let guard = opts.guard()?;
+    let results = undecodable.chain(by_path.iter().map(|(path, edits)| {
+        unprotected(&guard, path)?;
+        apply_edits(path, edits, &opener, opts.dry_run)
+    }));
+    if present_all(&presenter, results) {
+        return Err(io_err("some edits couldn't be applied"));
+    }
+    Ok(())
+}
+
+fn cow(opts: CowOpts) -> io::Result<()> {
+    let presenter = opts.presenter();
+    let guard = opts.guard()?;
+    let manifest = Manifest::new(&env::current_dir()?);
+    let copies = find_copies(opts.action.files(), opts.cow_suffix(), &manifest);
+    manifest.take_invalid().into_iter().for_each(|e| presenter.wax(e));
+    // Accepting one copy after another would just leave the last, so they have to pick one
+    let mut copy_counts: BTreeMap<&Path, usize> = BTreeMap::new();
+    for (_, original) in &copies {
+        *copy_counts.entry(original).or_default() += 1;
+    }
+    let mut refused = BTreeSet::new();
+    let results = copies.iter().filter_map(|(copy, original)| {
+        let result = match opts.action {
+            CowAction::Accept { .. } if copy_counts[original.as_path()] > 1 => {
+                if !refused.insert(original) {
+                    return None;
+                }
+                let path = original.clone();
+                Err(GsubEvent::Skipped { path, reason: SkipReason::SeveralCopies })
+            },
+            CowAction::Accept { .. } => {
+                unprotected(&guard, original).and_then(|_| cow::accept(copy, original))
+            },
+            CowAction::Discard { .. } => unprotected(&guard, copy).and_then(|_| cow::discard(copy)),
+            CowAction::Diff { .. } => return Some(cow::diff(copy, original)),
+        };
+        if result.is_ok() {
+            manifest.forget(copy);
+        }
+        Some(result)
     });
+    let failed = present_all(&presenter, results);
+    manifest.save()?;
+    if failed {
+        return Err(io_err("some copies couldn't be handled"));
+    }
+    Ok(())
+}
+
+fn run(opts: Opts) -> io::Result<()> {
+    let replacer = opts.replacer().map_err(io_err)?;
+    let exclusions = opts.exclusions().map_err(io_err)?;
+    let opener = opts.open_opts();
+    if opts.filtering_stdin() {
+        let presenter = opts.presenter(&Progress::new(false))?;
+        filter_stdin(&replacer, &opts, &presenter)?;
+        presenter.finish();
+        return Ok(());
+    }
+    let progress = opts.progress();
+    let presenter = opts.presenter(&progress)?;
+    let changes = opts.change_log()?;
+    let proposals = opts.proposals();
+    let visited = Visited::new();
+    let symlinks = opts.symlinks();
+    let refused_links = AtomicBool::new(false);
+    for (path, first) in &opts.duplicate_roots {
+        presenter.wax(GsubEvent::Duplicate { path: path.clone(), first: first.clone() });
+    }
+
+    let visit = |result| {
+        let entry = get_allowed_file_entry(
+            result,
+            &exclusions,
+            changes.manifest(),
+            &visited,
+            &symlinks,
+            opts.guard.as_ref(),
+            &|event| {
+                if let GsubEvent::Skipped { reason: SkipReason::LinkOutsideRoots, .. } = event {
+                    refused_links.store(true, Ordering::Relaxed);
+                }
+                presenter.wax(event)
+            },
+        );
+        let entry = match entry {
+            Ok(e) => e,
+            Err(walk_state) => return walk_state,
+        };
+        if progress.enabled() {
+            progress.visit(entry.metadata().map(|md| md.len()).unwrap_or(0));
+        }
+        let result = if opts.only_reading() {
+            search(entry, &opener, &replacer, &opts)
+        } else {
+            gsub(entry, &opener, &replacer, &opts, &changes, proposals.as_deref())
+        };
+        match result {
+            Ok(event) | Err(event) => {
+                if event.is_change() {
+                    progress.change();
+                }
+                presenter.wax(event)
+            },
+        }
+        WalkState::Continue
+    };
+
+    let file_list = opts.file_list()?;
+    thread::scope(|s| {
+        if progress.enabled() {
+            match file_list {
+                Some(_) => progress.no_count(),
+                None => { s.spawn(|| count_files(&opts, &exclusions, changes.manifest(), &symlinks, &progress)); },
+            }
+            s.spawn(|| progress.draw_until_finished());
+        }
+        let walked = match file_list {
+            Some((list, separator)) => walk_file_list(&opts, list, separator, visit),
+            None if opts.threads == Some(1) => {
+                walk_sequentially(&opts.walk_builder(), visit);
+                Ok(())
+            },
+            None => {
+                opts.walk_builder().build_parallel().run(|| Box::new(visit));
+                Ok(())
+            },
+        };
+        progress.finish();
+        walked
+    })?;
+    if let Some(manifest) = changes.manifest() {
+        manifest.take_invalid().into_iter().for_each(|e| presenter.wax(e));
+    }
+    presenter.flush_sorted();
+    changes.save_manifest()?;
+    if let Some(proposals) = &proposals {
+        opts.write_proposals(proposals.as_ref())?;
+    }
+
+    let mut exec_failed = false;
+    if let Some(cmd) = &opts.exec_changed {
+        for status in hooks::exec_batched(cmd, &changes.paths())? {
+            if !status.success() {
+                presenter.wax(GsubEvent::HookFailed { command: cmd.clone(), status: status.code() });
+                exec_failed = true;
+            }
+        }
+    }
+
+    if let Some(cmd) = &opts.verify {
+        // Not being able to run the check at all is as bad as it failing
+        let failure = match hooks::shell(cmd).status() {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(GsubEvent::VerifyFailed { command: cmd.clone(), status: status.code() }),
+            Err(e) => Some(GsubEvent::VerifyNotRun { command: cmd.clone(), kind: e.kind() }),
+        };
+        if let Some(failure) = failure {
+            presenter.wax(failure);
+            for (path, result) in changes.rollback() {
+                match result {
+                    Ok(()) => presenter.wax(GsubEvent::Restored { path }),
+                    Err(e) => presenter.wax(GsubEvent::RestoreFailed { path, kind: e.kind() }),
+                }
+            }
+            presenter.finish();
+            return Err(io_err("verification command failed"));
+        }
+    }
+
+    if opts.print0 {
+        let mut stdout = io::stdout().lock();
+        for path in changes.paths() {
+            stdout.write_all(path.as_os_str().as_encoded_bytes())?;
+            stdout.write_all(b"\0")?;
+        }
+        stdout.flush()?;
+    }
+
+    presenter.finish();
+    if exec_failed {
+        return Err(io_err("--exec-changed command failed"));
+    }
+    if refused_links.load(Ordering::Relaxed) {
+        return Err(io_err("some symlinks led outside the files given"));
+    }
 
     Ok(())
 }
\ No newline at end of file
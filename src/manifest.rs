@@ -0,0 +1,230 @@
+use std::{fs, io, process};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::event::GsubEvent;
+use crate::paths::{absolute, relative_to};
+
+/// Lives next to the copies it lists
+pub static MANIFEST_NAME: &str = ".gsub-manifest";
+
+/// One line of a manifest. Paths are relative to the manifest's directory.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    copy: PathBuf,
+    original: PathBuf,
+}
+
+/// The copies listed in one directory's manifest
+#[derive(Debug, Default)]
+struct Listing {
+    /// Absolute copy -> absolute original
+    copies: BTreeMap<PathBuf, PathBuf>,
+    changed: bool,
+    /// It's there but couldn't be read, so writing it back would lose what's in it
+    unreadable: bool,
+}
+
+/// Every copy `--copy-on-write` has made, so later runs can leave exactly those files alone
+/// (and `gsub cow` can find them), however they're named. Each directory with copies in it
+/// has its own manifest, so they're found wherever gsub is run from.
+#[derive(Debug)]
+pub struct Manifest {
+    cwd: PathBuf,
+    /// Absolute directory -> its manifest, read the first time a file in it comes up
+    dirs: Mutex<BTreeMap<PathBuf, Listing>>,
+    /// Lines that couldn't be read, which were left out
+    invalid: Mutex<Vec<GsubEvent>>,
+}
+
+impl Manifest {
+    /// Paths given to the manifest are relative to `cwd`
+    pub fn new(cwd: &Path) -> Self {
+        Self { cwd: cwd.to_path_buf(), dirs: Mutex::new(BTreeMap::new()), invalid: Mutex::new(Vec::new()) }
+    }
+
+    /// Runs `f` on the manifest for the directory `path` is in
+    fn in_dir_of<T>(&self, path: &Path, f: impl FnOnce(&Path, &mut Listing) -> T) -> Option<T> {
+        let path = absolute(&self.cwd, path);
+        let dir = path.parent()?;
+        let mut dirs = self.dirs.lock().unwrap();
+        let listing = dirs.entry(dir.to_path_buf()).or_insert_with(|| self.read(dir));
+        Some(f(&path, listing))
+    }
+
+    /// Reads the manifest in `dir`, forgetting copies that have since been deleted. Lines that
+    /// can't be read are left out and warned about, rather than stopping the run.
+    fn read(&self, dir: &Path) -> Listing {
+        let path = dir.join(MANIFEST_NAME);
+        let mut listing = Listing::default();
+        let invalid = |line, message: String| {
+            self.invalid.lock().unwrap().push(GsubEvent::InvalidManifest { path: path.clone(), line, message });
+        };
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return listing,
+            Err(e) => {
+                invalid(None, e.to_string());
+                listing.unreadable = true;
+                return listing;
+            },
+        };
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    invalid(Some(i + 1), e.to_string());
+                    listing.unreadable = true;
+                    break;
+                },
+            };
+            let entry: Entry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    invalid(Some(i + 1), e.to_string());
+                    continue;
+                },
+            };
+            let copy = absolute(dir, &entry.copy);
+            if copy.is_file() {
+                listing.copies.insert(copy, absolute(dir, &entry.original));
+            } else {
+                listing.changed = true;
+            }
+        }
+        listing
+    }
+
+    /// Whether gsub made `path`, including manifests themselves
+    pub fn made(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| name == MANIFEST_NAME) || self.original_of(path).is_some()
+    }
+
+    /// The file gsub copied to make `copy`, as a sibling of `copy`
+    pub fn original_of(&self, copy: &Path) -> Option<PathBuf> {
+        let original = self.in_dir_of(copy, |copy, listing| listing.copies.get(copy).cloned())??;
+        Some(copy.with_file_name(original.file_name()?))
+    }
+
+    /// Every copy gsub made of `original`, as siblings of it
+    pub fn copies_of(&self, original: &Path) -> Vec<PathBuf> {
+        let copies = self.in_dir_of(original, |original, listing| {
+            listing.copies.iter()
+                .filter(|(_, o)| o.as_path() == original)
+                .filter_map(|(copy, _)| copy.file_name().map(|name| name.to_os_string()))
+                .collect::<Vec<_>>()
+        });
+        copies.unwrap_or_default().into_iter().map(|name| original.with_file_name(name)).collect()
+    }
+
+    pub fn record(&self, copy: &Path, original: &Path) {
+        let original = absolute(&self.cwd, original);
+        self.in_dir_of(copy, |copy, listing| {
+            listing.copies.insert(copy.to_path_buf(), original);
+            listing.changed = true;
+        });
+    }
+
+    pub fn forget(&self, copy: &Path) {
+        self.in_dir_of(copy, |copy, listing| {
+            if listing.copies.remove(copy).is_some() {
+                listing.changed = true;
+            }
+        });
+    }
+
+    /// Warnings about manifest lines that couldn't be read, since the last call
+    pub fn take_invalid(&self) -> Vec<GsubEvent> {
+        std::mem::take(&mut self.invalid.lock().unwrap())
+    }
+
+    /// Writes back every manifest that changed, deleting the ones with nothing left in them
+    pub fn save(&self) -> io::Result<()> {
+        let dirs = self.dirs.lock().unwrap();
+        for (dir, listing) in dirs.iter().filter(|(_, l)| l.changed && !l.unreadable) {
+            save_listing(dir, listing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes to a temporary file first, so a manifest is never left half written
+fn save_listing(dir: &Path, listing: &Listing) -> io::Result<()> {
+    let path = dir.join(MANIFEST_NAME);
+    if listing.copies.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    let temp = dir.join(format!("{}.{}.tmp", MANIFEST_NAME, process::id()));
+    let written = (|| {
+        let mut out = BufWriter::new(fs::File::create(&temp)?);
+        for (copy, original) in &listing.copies {
+            let entry = Entry { copy: relative_to(dir, copy), original: relative_to(dir, original) };
+            serde_json::to_writer(&mut out, &entry)?;
+            writeln!(out)?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp, &path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_copies_between_runs() {
+        let cwd = std::env::current_dir().unwrap();
+        let dir = absolute(&cwd, Path::new("test-files/manifest"));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("milk.txt.gsub"), "oat milk").unwrap();
+        fs::write(dir.join("milk.txt.1.gsub"), "rice milk").unwrap();
+        fs::write(dir.join("gone.gsub"), "soy milk").unwrap();
+
+        let manifest = Manifest::new(&cwd);
+        manifest.record(Path::new("test-files/manifest/milk.txt.gsub"), Path::new("test-files/manifest/milk.txt"));
+        manifest.record(Path::new("test-files/manifest/milk.txt.1.gsub"), Path::new("test-files/manifest/milk.txt"));
+        manifest.record(Path::new("test-files/manifest/gone.gsub"), Path::new("test-files/manifest/gone"));
+        manifest.save().unwrap();
+        fs::remove_file(dir.join("gone.gsub")).unwrap();
+
+        // From somewhere else, the manifest next to the copies is still found
+        let reloaded = Manifest::new(&dir);
+        let made_copy = reloaded.made(Path::new("./milk.txt.gsub"));
+        let made_original = reloaded.made(Path::new("milk.txt"));
+        let made_manifest = reloaded.made(Path::new(MANIFEST_NAME));
+        let original = reloaded.original_of(Path::new("milk.txt.1.gsub"));
+        let copies = reloaded.copies_of(Path::new("milk.txt"));
+        let forgot_gone = !reloaded.made(Path::new("gone.gsub"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(made_copy && made_manifest && !made_original && forgot_gone);
+        assert_eq!(original, Some(PathBuf::from("milk.txt")));
+        assert_eq!(copies, [PathBuf::from("milk.txt.1.gsub"), PathBuf::from("milk.txt.gsub")]);
+    }
+
+    #[test]
+    fn skips_lines_it_cant_read() {
+        let cwd = std::env::current_dir().unwrap();
+        let dir = absolute(&cwd, Path::new("test-files/manifest-invalid"));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("milk.txt.gsub"), "oat milk").unwrap();
+        fs::write(dir.join(MANIFEST_NAME), "not json\n{\"copy\":\"milk.txt.gsub\",\"original\":\"milk.txt\"}\n").unwrap();
+
+        let manifest = Manifest::new(&dir);
+        let made_copy = manifest.made(Path::new("milk.txt.gsub"));
+        let invalid = manifest.take_invalid();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(made_copy);
+        assert!(matches!(invalid.as_slice(), [GsubEvent::InvalidManifest { line: Some(1), .. }]));
+    }
+}
@@ -0,0 +1,135 @@
+use std::{fs, io};
+use std::fs::{File, FileTimes, Metadata};
+use std::path::Path;
+
+/// Gives `to`, a file written in place of `from`, the same owner, extended attributes and
+/// permissions, and if `times`, the same access and modification times. Only failing to copy
+/// the permissions is an error, since changing owner usually takes root, and some attributes
+/// (like SELinux labels) can't be set by just anyone either.
+pub fn copy_metadata(from: &Path, md: &Metadata, to: &Path, times: bool) -> io::Result<()> {
+    // Before the permissions, which might not let us open the file for writing
+    if times {
+        restore_times(&File::options().write(true).open(to)?, md)?;
+    }
+    copy_owner(md, to);
+    copy_xattrs(from, to);
+    fs::set_permissions(to, md.permissions())
+}
+
+/// Sets `file`'s access and modification times back to the ones in `md`
+pub fn restore_times(file: &File, md: &Metadata) -> io::Result<()> {
+    let mut times = FileTimes::new();
+    if let Ok(modified) = md.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = md.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    file.set_times(times)
+}
+
+/// The (device, inode) that tells files apart, where there is one
+#[cfg(unix)]
+pub fn file_id(md: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn copy_owner(md: &Metadata, to: &Path) {
+    use std::os::unix::fs::{chown, MetadataExt};
+    let _ = chown(to, Some(md.uid()), Some(md.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_: &Metadata, _: &Path) {}
+
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path) {
+    let names = match xattr::list(from) {
+        Ok(names) => names,
+        Err(_) => return,
+    };
+    for name in names {
+        if let Ok(Some(value)) = xattr::get(from, &name) {
+            let _ = xattr::set(to, &name, &value);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_: &Path, _: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn copies_permissions_and_times() {
+        let (from, to) = ("test-files/metadata-from", "test-files/metadata-to");
+        fs::write(from, "soy milk").unwrap();
+        fs::write(to, "oat milk").unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        File::options().write(true).open(from).unwrap()
+            .set_times(FileTimes::new().set_modified(an_hour_ago))
+            .unwrap();
+        let mut readonly = fs::metadata(from).unwrap().permissions();
+        readonly.set_readonly(true);
+        fs::set_permissions(from, readonly).unwrap();
+
+        let md = fs::metadata(from).unwrap();
+        let copied = copy_metadata(Path::new(from), &md, Path::new(to), true);
+        let to_md = fs::metadata(to).unwrap();
+        fs::remove_file(from).unwrap();
+        fs::remove_file(to).unwrap();
+        assert!(copied.is_ok());
+        assert!(to_md.permissions().readonly());
+        assert_eq!(to_md.modified().unwrap(), an_hour_ago);
+    }
+
+    #[test]
+    fn restores_times_after_writing_in_place() {
+        let path = "test-files/metadata-in-place";
+        fs::write(path, "soy milk").unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        File::options().write(true).open(path).unwrap()
+            .set_times(FileTimes::new().set_modified(an_hour_ago))
+            .unwrap();
+
+        let mut file = File::options().write(true).open(path).unwrap();
+        let md = file.metadata().unwrap();
+        io::Write::write_all(&mut file, b"oat milk").unwrap();
+        let restored = restore_times(&file, &md);
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(restored.is_ok());
+        assert_eq!(modified, an_hour_ago);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_xattrs_where_supported() {
+        let (from, to) = ("test-files/metadata-xattr-from", "test-files/metadata-xattr-to");
+        fs::write(from, "soy milk").unwrap();
+        fs::write(to, "oat milk").unwrap();
+        // Not every filesystem supports user attributes, and there's nothing to test on those
+        if xattr::set(from, "user.gsub.milk", b"oat").is_err() {
+            fs::remove_file(from).unwrap();
+            fs::remove_file(to).unwrap();
+            return;
+        }
+        let md = fs::metadata(from).unwrap();
+        let copied = copy_metadata(Path::new(from), &md, Path::new(to), false);
+        let value = xattr::get(to, "user.gsub.milk").unwrap();
+        fs::remove_file(from).unwrap();
+        fs::remove_file(to).unwrap();
+        assert!(copied.is_ok());
+        assert_eq!(value.as_deref(), Some(&b"oat"[..]));
+    }
+}
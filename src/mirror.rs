@@ -0,0 +1,103 @@
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use crate::paths::absolute;
+
+/// Where `--output-dir` mirrors the files/directories given to. Everything keeps its path
+/// relative to the deepest directory they're all in, so a single directory's contents go
+/// directly under the output directory, and several roots can't land on top of each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    cwd: PathBuf,
+    /// The deepest directory every root is in
+    base: PathBuf,
+    /// As the user gave it, so the paths we print look familiar
+    output: PathBuf,
+    absolute_output: PathBuf,
+}
+
+impl Mirror {
+    pub fn new(cwd: &Path, roots: &[PathBuf], output: &Path) -> Self {
+        // A file given by name goes in by its name, so it's mirrored from the directory it's in
+        let mut dirs = roots.iter().map(|r| absolute(cwd, r)).map(|r| match r.parent() {
+            Some(parent) if r.is_file() => parent.to_path_buf(),
+            _ => r,
+        });
+        let first = dirs.next().unwrap_or_else(|| cwd.to_path_buf());
+        let base = dirs.fold(first, |base, dir| {
+            base.components().zip(dir.components()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+        });
+        Self {
+            cwd: cwd.to_path_buf(),
+            base,
+            output: output.to_path_buf(),
+            absolute_output: absolute(cwd, output),
+        }
+    }
+
+    /// Where `path` goes in the output directory. Files outside the roots can't be mirrored.
+    pub fn path_for(&self, path: &Path) -> Option<PathBuf> {
+        let path = absolute(&self.cwd, path);
+        let relative = path.strip_prefix(&self.base).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+        Some(self.output.join(relative))
+    }
+
+    /// Whether `path` is the output directory itself, which we mustn't walk into
+    pub fn is_output(&self, path: &Path) -> bool {
+        absolute(&self.cwd, path) == self.absolute_output
+    }
+
+    pub fn write(&self, to: &Path, contents: &[u8]) -> io::Result<()> {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(to, contents)
+    }
+
+    pub fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrors_paths_under_the_source_tree() {
+        let mirror = Mirror::new(Path::new("/repo/template"), &[PathBuf::from(".")], Path::new("../variant"));
+        assert_eq!(
+            mirror.path_for(Path::new("./src/../src/main.rs")),
+            Some(PathBuf::from("../variant/src/main.rs"))
+        );
+        assert_eq!(mirror.path_for(Path::new("../elsewhere.rs")), None);
+        assert!(mirror.is_output(Path::new("../variant/")));
+        assert!(!mirror.is_output(Path::new("variant")));
+    }
+
+    #[test]
+    fn mirrors_several_roots_without_overlapping() {
+        let cwd = std::env::current_dir().unwrap();
+        fs::create_dir_all("test-files/mirror/src").unwrap();
+        fs::create_dir_all("test-files/mirror/docs").unwrap();
+        fs::write("test-files/mirror/docs/README.md", "oat milk").unwrap();
+        let mirror_of = |roots: &[&str]| {
+            Mirror::new(&cwd, &roots.iter().map(PathBuf::from).collect::<Vec<_>>(), Path::new("out"))
+        };
+        let several = mirror_of(&["test-files/mirror/src", "test-files/mirror/docs"]);
+        let one = mirror_of(&["test-files/mirror/src"]);
+        let file = mirror_of(&["test-files/mirror/docs/README.md"]);
+        fs::remove_dir_all("test-files/mirror").unwrap();
+
+        assert_eq!(several.path_for(Path::new("test-files/mirror/src/README.md")), Some(PathBuf::from("out/src/README.md")));
+        assert_eq!(several.path_for(Path::new("test-files/mirror/docs/README.md")), Some(PathBuf::from("out/docs/README.md")));
+        assert_eq!(one.path_for(Path::new("test-files/mirror/src/main.rs")), Some(PathBuf::from("out/main.rs")));
+        assert_eq!(file.path_for(Path::new("test-files/mirror/docs/README.md")), Some(PathBuf::from("out/README.md")));
+        assert_eq!(one.path_for(Path::new("test-files/mirror/docs/README.md")), None);
+    }
+}
@@ -1,15 +1,49 @@
+use std::env;
+use std::ffi::OsString;
 use std::path::PathBuf;
-use std::fs::OpenOptions;
+use std::thread;
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::fs::{File, OpenOptions};
 use structopt::StructOpt;
 use ignore::WalkBuilder;
-use regex::{self, RegexSet};
-use crate::CURRENT_DIR;
+use regex;
+use crate::{CURRENT_DIR, STDIN_PATH};
 use crate::replacer::Replacer;
-use crate::presenter::Presenter;
-use crate::gsub::GSUB_EXT_PATTERN;
+use crate::presenter::{Format, Presenter, SortBy};
+use crate::event::Level;
+use crate::progress::Progress;
+use crate::color::ColorChoice;
+use crate::changes::ChangeLog;
+use crate::mirror::Mirror;
+use crate::paths::dedupe_roots;
+use crate::symlinks::{OutsideLinks, Symlinks};
+use crate::guard::Guard;
+use crate::exclude::{Exclusions, GSUBIGNORE_NAME};
+use crate::patch::Patch;
+use crate::plan::{read_plan, Plan, Planner};
+use crate::proposals::Proposals;
+use crate::edits::{read_edits, Edits};
+use crate::editor::{Quickfix, WorkspaceEdits};
+use crate::gsub::{Collision, GSUB_EXT_NAME};
+use crate::manifest::Manifest;
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "gsub", about = "Regex substitution for files and directories")]
+#[structopt(
+    name = "gsub",
+    about = "Regex substitution for files and directories",
+    after_help = "SUBCOMMANDS:
+    gsub plan [OPTIONS] <pattern> <replacement> [files]...
+        Print every edit gsub would make as a JSON plan, instead of making them
+    gsub apply [OPTIONS] <plan>
+        Make the edits left in a plan, skipping files that changed since it was made
+    gsub apply-edits [OPTIONS] [edits]
+        Make edits listed as JSON by other tools, or found by `rg --json`
+    gsub cow <accept|discard|diff> [files]...
+        Replace originals with the copies --copy-on-write made, delete the copies, or diff them
+
+    A subcommand's name only counts as one when it comes first. To replace one of the words
+    themselves, put -- or another option before it: gsub -- plan blueprint notes.txt",
+)]
 pub struct Opts {
     #[structopt(short, long)]
     pub dry_run: bool,
@@ -18,51 +52,550 @@ pub struct Opts {
     #[structopt(short, long)]
     pub copy_on_write: bool,
 
+    /// What to add to the end of a file's name to name its copy
+    #[structopt(long, value_name = "suffix", default_value = GSUB_EXT_NAME)]
+    pub cow_suffix: String,
+
+    /// What to do when a file's copy already exists
+    #[structopt(long, value_name = "policy", default_value = "error", possible_values = Collision::VARIANTS)]
+    pub on_collision: Collision,
+
+    /// Write changed files into a mirror of the files/directories given under dir, instead of editing them
+    #[structopt(long, parse(from_os_str), value_name = "dir", conflicts_with = "copy-on-write")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Copy unchanged files into --output-dir too. Files gsub doesn't visit (ignored, hidden, too big) still aren't copied
+    #[structopt(long, requires = "output-dir")]
+    pub copy_unchanged: bool,
+
+    /// Keep the modification times of changed files, and give copies their original's
+    #[structopt(long)]
+    pub preserve_mtime: bool,
+
+    /// Print skipped files too. Pass twice to trace every file visited
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[structopt(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Only print errors, and a summary once finished
+    #[structopt(long)]
+    pub summary_only: bool,
+
+    /// Don't show a progress display, even when stderr is a terminal
+    #[structopt(long)]
+    pub no_progress: bool,
+
+    /// Write everything -vv would print to a file
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    pub log_file: Option<PathBuf>,
+
+    /// Take an advisory lock on each file while editing it, skipping files locked by others
+    #[structopt(long)]
+    pub lock: bool,
+
+    /// Command to run after all edits. If it fails, every edited file is restored
+    #[structopt(long, value_name = "cmd")]
+    pub verify: Option<String>,
+
+    /// Command to run on the changed files, e.g. "rustfmt {}". Files are passed in batches
+    #[structopt(long, value_name = "cmd")]
+    pub exec_changed: Option<String>,
+
+    /// How to print results. lsp-workspace-edit and quickfix print the changes for an editor to
+    /// make, instead of making them
+    #[structopt(long, default_value = "text", possible_values = Format::VARIANTS)]
+    pub format: Format,
+
+    /// Buffer results and print them in order, instead of as soon as they're ready
+    #[structopt(long, possible_values = SortBy::VARIANTS)]
+    pub sort: Option<SortBy>,
+
+    /// Number of threads to walk with. 1 walks sequentially, and 0 picks a number for you
+    #[structopt(short = "j", long)]
+    pub threads: Option<usize>,
+
+    /// When to use colors and hyperlinks
+    #[structopt(long, default_value = "auto", possible_values = ColorChoice::VARIANTS)]
+    pub color: ColorChoice,
+
+    /// Print the changed files to stdout, separated by NUL bytes
+    #[structopt(long)]
+    pub print0: bool,
+
+    /// Write a patch of every change to a file (or - for stdout) instead of editing anything
+    #[structopt(long, parse(from_os_str), value_name = "file")]
+    pub patch_out: Option<PathBuf>,
+
+    /// Print matching lines instead of replacing anything
     #[structopt(short, long)]
-    pub verbose: bool,
+    pub search: bool,
+
+    /// Only print the names of files with matches
+    #[structopt(short = "l", long)]
+    pub files_with_matches: bool,
+
+    /// Only print how many matches each file has
+    #[structopt(long)]
+    pub count: bool,
+
+    /// Lines of context to show around each match when searching
+    #[structopt(short = "C", long, default_value = "0", value_name = "lines")]
+    pub context: usize,
+
+    /// Print the replacement for each match, instead of editing files
+    #[structopt(short, long, conflicts_with_all = &["search", "files-with-matches", "count"])]
+    pub only_output: bool,
 
     /// The pattern you want to replace
     pub pattern: String,
 
-    /// String for replacement
-    pub replacement: String,
+    /// String for replacement. Not needed when searching
+    #[structopt(required_unless_one = &["search", "files-with-matches", "count"])]
+    pub replacement: Option<String>,
+
+    /// Read the files/directories to gsub on from a file, one per line. Pass - to use stdin
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    pub files_from: Option<PathBuf>,
+
+    /// Paths given to --files-from are separated by NUL bytes, instead of newlines
+    #[structopt(short = "0", long)]
+    pub null: bool,
 
     /// Skip files larger than the given number of bytes.
     #[structopt(short = "m", long = "skip-files-larger-than", default_value = "4194304")]
     pub max_file_size: u64,
 
-    /// Files/Directories to skip
+    /// Files/Directories to skip, by a regex matching their names
     #[structopt(short = "e", long = "except")]
     pub files_to_skip: Vec<String>,
 
+    /// Files/Directories to skip, by a regex matching their path from the current directory
+    #[structopt(long, value_name = "regex", number_of_values = 1)]
+    pub except_path: Vec<String>,
+
+    /// Files/Directories to skip, by a gitignore-style glob, e.g. "/src/generated/". Globs in
+    /// .gsubignore files are skipped too
+    #[structopt(long, value_name = "glob", number_of_values = 1)]
+    pub exclude: Vec<String>,
+
     /// Do not skip hidden files and directories
     #[structopt(short = "h", long = "hidden")]
     pub show_hidden_files: bool,
 
-    /// List of files/directories you want to gsub on. If unspecified, uses the current directory.
+    /// Don't skip files ignored by .gitignore, .ignore, .gsubignore or other ignore files
+    #[structopt(long)]
+    pub no_ignore: bool,
+
+    /// Don't skip files ignored by .gitignore, .git/info/exclude or git's global excludes
+    #[structopt(long)]
+    pub no_ignore_vcs: bool,
+
+    /// Don't read ignore files in the directories above the files/directories given
+    #[structopt(long)]
+    pub no_ignore_parent: bool,
+
+    /// Same as --no-ignore. Pass twice to add --hidden. Large files are still skipped, see -m
+    #[structopt(short = "u", long = "unrestricted", parse(from_occurrences))]
+    pub unrestricted: u8,
+
+    /// Don't descend more than this many directories below the files/directories given
+    #[structopt(long, value_name = "n")]
+    pub max_depth: Option<usize>,
+
+    /// Don't cross into other file systems from the files/directories given
+    #[structopt(long)]
+    pub one_file_system: bool,
+
+    /// Never edit files matching this glob, e.g. "*.lock" or "vendor/**". Can be repeated
+    #[structopt(long, value_name = "glob", number_of_values = 1)]
+    pub protect: Vec<String>,
+
+    /// Follow symlinks, editing the files they point to
+    #[structopt(short = "L", long)]
+    pub follow_symlinks: bool,
+
+    /// What --follow-symlinks does with symlinks that point outside the files/directories given
+    #[structopt(long, value_name = "policy", default_value = "skip", possible_values = OutsideLinks::VARIANTS)]
+    pub outside_symlinks: OutsideLinks,
+
+    /// Edit the files that symlinks given as files point to, without following any others
+    #[structopt(long)]
+    pub follow_named_symlinks: bool,
+
+    /// List of files/directories you want to gsub on. If unspecified, uses the current directory,
+    /// or stdin if it's piped. Pass - to read stdin and write the result to stdout
     #[structopt(parse(from_os_str))]
     pub files: Vec<PathBuf>,
+
+    /// Set by `gsub plan`, which prints a plan instead of editing anything
+    #[structopt(skip)]
+    pub planning: bool,
+
+    /// Filled in from `output_dir` once we know the current directory
+    #[structopt(skip)]
+    pub mirror: Option<Mirror>,
+
+    /// Roots dropped for resolving to the same place as an earlier one, and which one
+    #[structopt(skip)]
+    pub duplicate_roots: Vec<(PathBuf, PathBuf)>,
+
+    /// Built from the roots and `protect` once they're validated
+    #[structopt(skip)]
+    pub guard: Option<Guard>,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "gsub apply", about = "Makes the edits in a plan written by `gsub plan`")]
+pub struct ApplyOpts {
+    /// Print what would change, without changing anything
+    #[structopt(short, long)]
+    pub dry_run: bool,
+
+    /// Print unchanged files too
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[structopt(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// How to print what happened
+    #[structopt(long, default_value = "text", possible_values = Format::EVENT_VARIANTS)]
+    pub format: Format,
+
+    /// When to use colors and hyperlinks
+    #[structopt(long, default_value = "auto", possible_values = ColorChoice::VARIANTS)]
+    pub color: ColorChoice,
+
+    /// Take an advisory lock on each file while editing it, skipping files locked by others
+    #[structopt(long)]
+    pub lock: bool,
+
+    /// Never edit files matching this glob, e.g. "*.lock" or "vendor/**". Can be repeated
+    #[structopt(long, value_name = "glob", number_of_values = 1)]
+    pub protect: Vec<String>,
+
+    /// The plan to apply. Pass - to read it from stdin
+    #[structopt(parse(from_os_str))]
+    pub plan: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "gsub apply-edits",
+    about = "Makes edits listed as JSON by other tools",
+    after_help = "Edits are read as JSON arrays, or one per line, like:
+    {\"path\": \"src/main.rs\", \"start\": 120, \"end\": 127, \"replacement\": \"gabagool\"}
+where start and end are byte offsets. They can also be 1-based lines and (byte) columns:
+    \"start\": {\"line\": 3, \"column\": 5}, \"end\": {\"line\": 3, \"column\": 12}
+The output of `rg --json` works too, replacing each match with --replacement.",
+)]
+pub struct ApplyEditsOpts {
+    /// Print what would change, without changing anything
+    #[structopt(short, long)]
+    pub dry_run: bool,
+
+    /// Print unchanged files too
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[structopt(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// How to print what happened
+    #[structopt(long, default_value = "text", possible_values = Format::EVENT_VARIANTS)]
+    pub format: Format,
+
+    /// When to use colors and hyperlinks
+    #[structopt(long, default_value = "auto", possible_values = ColorChoice::VARIANTS)]
+    pub color: ColorChoice,
+
+    /// What to replace ripgrep's matches with
+    #[structopt(short, long)]
+    pub replacement: Option<String>,
+
+    /// Never edit files matching this glob, e.g. "*.lock" or "vendor/**". Can be repeated
+    #[structopt(long, value_name = "glob", number_of_values = 1)]
+    pub protect: Vec<String>,
+
+    /// File of edits to make. Reads stdin if unspecified, or -
+    #[structopt(parse(from_os_str), default_value = "-")]
+    pub edits: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "gsub cow", about = "Deals with the .gsub copies made by --copy-on-write")]
+pub struct CowOpts {
+    /// Print unchanged files too
+    #[structopt(short, long, global = true, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[structopt(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// How to print what happened
+    #[structopt(long, global = true, default_value = "text", possible_values = Format::EVENT_VARIANTS)]
+    pub format: Format,
+
+    /// When to use colors and hyperlinks
+    #[structopt(long, global = true, default_value = "auto", possible_values = ColorChoice::VARIANTS)]
+    pub color: ColorChoice,
+
+    /// The suffix the copies were made with
+    #[structopt(long, global = true, value_name = "suffix", default_value = GSUB_EXT_NAME)]
+    pub cow_suffix: String,
+
+    /// Never write to files matching this glob, e.g. "*.lock" or "vendor/**". Can be repeated
+    #[structopt(long, global = true, value_name = "glob", number_of_values = 1)]
+    pub protect: Vec<String>,
+
+    #[structopt(subcommand)]
+    pub action: CowAction,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CowAction {
+    /// Replace each original with its copy. Originals with more than one copy are left alone,
+    /// unless the copy to accept is named
+    Accept {
+        /// Files/directories to look for copies in. If unspecified, uses the current directory
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+    /// Delete the copies
+    Discard {
+        /// Files/directories to look for copies in. If unspecified, uses the current directory
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+    /// Print a patch of how each copy differs from its original
+    Diff {
+        /// Files/directories to look for copies in. If unspecified, uses the current directory
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+}
+
+impl CowAction {
+    pub fn files(&self) -> &[PathBuf] {
+        match self {
+            CowAction::Accept { files } | CowAction::Discard { files } | CowAction::Diff { files } => files,
+        }
+    }
+}
+
+/// Keeps the subcommands, which are told exactly which files to write, away from version
+/// control directories and `--protect`ed files, wherever those files are
+fn protecting(protect: &[String]) -> io::Result<Guard> {
+    Guard::new(&[PathBuf::from(CURRENT_DIR)], protect)
+        .map(|guard| guard.confined(false))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--protect was given an invalid glob"))
+}
+
+fn max_level(quiet: bool, verbose: u8) -> Level {
+    match (quiet, verbose) {
+        (true, _) => Level::Error,
+        (false, 0) => Level::Important,
+        (false, 1) => Level::Verbose,
+        (false, _) => Level::Trace,
+    }
 }
 
 impl Opts {
     pub fn parse() -> Result<Self, &'static str> {
-        let opts = Self::from_args();
+        Self::from_args().validated()
+    }
+
+    /// Options for `gsub plan`, which takes the same ones as a normal run
+    pub fn parse_plan(args: impl IntoIterator<Item = OsString>) -> Result<Self, &'static str> {
+        let mut opts = Self::from_iter(args);
+        opts.planning = true;
+        opts.validated()
+    }
+
+    fn validated(self) -> Result<Self, &'static str> {
+        let mut opts = self;
+        if opts.searching() {
+            if let Some(first_file) = opts.replacement.take() {
+                // Without a replacement, the second positional argument is really a path
+                opts.files.insert(0, PathBuf::from(first_file));
+            }
+        }
+        if opts.files.len() > 1 && opts.files.iter().any(|f| f.as_os_str() == STDIN_PATH) {
+            return Err("- reads stdin, so it can't be combined with other files");
+        }
+        let modifying = opts.copying() || opts.verify.is_some() || opts.exec_changed.is_some();
+        let proposal_modes = [opts.planning, opts.patch_out.is_some(), opts.format.proposes()];
+        if proposal_modes.iter().filter(|&&on| on).count() > 1 {
+            return Err("only one of gsub plan, --patch-out and the editor formats can be used at once");
+        }
+        if opts.proposing() && (modifying || opts.only_reading()) {
+            return Err("gsub plan, --patch-out and the editor formats don't modify files, so they \
+                can't be combined with --copy-on-write, --output-dir, --verify, --exec-changed or \
+                searching");
+        }
+        if opts.filtering_stdin() && (modifying || opts.dry_run) {
+            return Err("stdin is filtered to stdout when no files are given, so it can't be \
+                combined with --dry-run, --copy-on-write, --verify or --exec-changed. Pass . to \
+                work on the current directory instead");
+        }
+        if opts.proposing() && opts.files.iter().any(|f| f.as_os_str() == STDIN_PATH) {
+            return Err("gsub plan, --patch-out and the editor formats need files to work on, so \
+                they can't read from stdin");
+        }
+        if opts.only_reading() && modifying {
+            return Err("searching doesn't modify files, so it can't be combined with \
+                --copy-on-write, --output-dir, --verify or --exec-changed");
+        }
         if opts.copy_on_write && opts.dry_run {
             return Err("--dry-run and --copy-on-write are incompatible flags");
         }
+        if opts.output_dir.is_some() && opts.dry_run {
+            return Err("--dry-run and --output-dir are incompatible flags");
+        }
+        if opts.cow_suffix().is_empty() || opts.cow_suffix.contains(std::path::is_separator) {
+            return Err("--cow-suffix has to be part of a file name");
+        }
+        if opts.verify.is_some() && (opts.copying() || opts.dry_run) {
+            return Err("--verify only makes sense when editing files in place");
+        }
+        if opts.output_dir.is_some() && opts.files.iter().any(|f| f.as_os_str() == STDIN_PATH) {
+            return Err("--output-dir mirrors files, so it can't read from stdin");
+        }
+        let (roots, duplicates) = dedupe_roots(std::mem::take(&mut opts.files));
+        opts.files = roots;
+        opts.duplicate_roots = duplicates;
+        let guard = Guard::new(&opts.roots(), &opts.protect).map_err(|_| "--protect was given an invalid glob")?;
+        let unconfined = opts.follow_symlinks && opts.outside_symlinks == OutsideLinks::Follow;
+        opts.guard = Some(guard.confined(!unconfined));
+        if let Some(dir) = &opts.output_dir {
+            let cwd = env::current_dir().map_err(|_| "couldn't find the current directory")?;
+            opts.mirror = Some(Mirror::new(&cwd, &opts.roots(), dir));
+        }
         Ok(opts)
     }
 
-    pub fn replacer(&self) -> Result<Replacer, regex::Error> {
-        Replacer::new(&self.pattern, &self.replacement)
+    /// Whether changes are written somewhere other than the files themselves
+    pub fn copying(&self) -> bool {
+        self.copy_on_write || self.output_dir.is_some()
     }
 
-    pub fn presenter(&self) -> Presenter {
-        Presenter::new(self.verbose)
+    /// Whether we're only looking for matches, not replacing them
+    pub fn searching(&self) -> bool {
+        self.search || self.files_with_matches || self.count
+    }
+
+    /// Whether to act as a filter from stdin to stdout, instead of walking files
+    pub fn filtering_stdin(&self) -> bool {
+        if self.files_from.is_some() || self.proposing() || self.output_dir.is_some() {
+            return false;
+        }
+        match self.files.as_slice() {
+            [] => stdin_is_piped(),
+            [only] => only.as_os_str() == STDIN_PATH,
+            _ => false,
+        }
+    }
+
+    /// Whether we only print things about files, rather than changing them
+    pub fn only_reading(&self) -> bool {
+        self.searching() || self.only_output
+    }
+
+    pub fn replacer(&self) -> Result<Replacer<'_>, regex::Error> {
+        Replacer::new(&self.pattern, self.replacement.as_deref().unwrap_or_default())
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress::new(!self.no_progress && !self.quiet)
+    }
+
+    pub fn presenter(&self, progress: &Progress) -> io::Result<Presenter> {
+        let mut presenter = Presenter::new(max_level(self.quiet, self.verbose), self.format)
+            .summary_only(self.summary_only)
+            .stdout_reserved(self.print0 || self.proposing_to_stdout())
+            .sort_by(self.sort)
+            .color(self.color)
+            .under_progress(progress.enabled());
+        if let Some(path) = &self.log_file {
+            presenter = presenter.log_to(File::create(path)?);
+        }
+        Ok(presenter)
+    }
+
+    /// Whether we're leaving files as they are, and only reporting what would change
+    pub fn dry_running(&self) -> bool {
+        self.dry_run || self.proposing()
+    }
+
+    /// Whether we're writing a patch or plan of the changes, instead of making them
+    fn proposing(&self) -> bool {
+        self.planning || self.patch_out.is_some() || self.format.proposes()
+    }
+
+    fn proposing_to_stdout(&self) -> bool {
+        match &self.patch_out {
+            Some(path) => path.as_os_str() == STDIN_PATH,
+            None => self.proposing(),
+        }
+    }
+
+    pub fn proposals(&self) -> Option<Box<dyn Proposals>> {
+        if self.planning {
+            let replacement = self.replacement.as_deref().unwrap_or_default();
+            Some(Box::new(Planner::new(&self.pattern, replacement)))
+        } else if self.patch_out.is_some() {
+            Some(Box::new(Patch::new()))
+        } else {
+            match self.format {
+                Format::LspWorkspaceEdit => Some(Box::new(WorkspaceEdits::new())),
+                Format::Quickfix => Some(Box::new(Quickfix::new())),
+                Format::Text | Format::Json => None,
+            }
+        }
+    }
+
+    /// Writes `proposals` to the `--patch-out` file, or stdout
+    pub fn write_proposals(&self, proposals: &dyn Proposals) -> io::Result<()> {
+        match &self.patch_out {
+            Some(path) if !self.proposing_to_stdout() => {
+                proposals.write_to(&mut BufWriter::new(File::create(path)?))
+            },
+            _ => proposals.write_to(&mut io::stdout().lock()),
+        }
+    }
+
+    /// The suffix without a leading `.`, which `cow_path` adds back
+    pub fn cow_suffix(&self) -> &str {
+        self.cow_suffix.trim_start_matches('.')
+    }
+
+    pub fn change_log(&self) -> io::Result<ChangeLog> {
+        // Every run reads the manifests, so copies gsub made are never edited like anything else
+        let changes = ChangeLog::new(self.verify.is_some());
+        Ok(changes.with_manifest(Manifest::new(&env::current_dir()?)))
+    }
+
+    /// What edits are kept inside. With `--files-from`, that's the current directory.
+    pub fn roots(&self) -> Vec<PathBuf> {
+        if self.files.is_empty() || self.files_from.is_some() {
+            vec![PathBuf::from(CURRENT_DIR)]
+        } else {
+            self.files.clone()
+        }
+    }
+
+    pub fn symlinks(&self) -> Symlinks {
+        Symlinks::new(self.follow_symlinks, self.follow_named_symlinks, self.outside_symlinks, &self.roots())
     }
 
     pub fn open_opts(&self) -> OpenOptions {
-        let read_only = self.copy_on_write || self.dry_run;
+        let read_only = self.copying() || self.dry_running() || self.only_reading();
         let mut open_opts = OpenOptions::new();
         open_opts.read(true)
             .write(!read_only)
@@ -71,27 +604,147 @@ impl Opts {
         open_opts
     }
 
-    pub fn dir_entry_blacklist(&self) -> Result<RegexSet, regex::Error> {
-        RegexSet::new([GSUB_EXT_PATTERN.to_string()]
-            .iter()
-            .chain(self.files_to_skip.iter())
-        )
+    pub fn exclusions(&self) -> Result<Exclusions, String> {
+        let cwd = env::current_dir().map_err(|e| e.to_string())?;
+        Exclusions::new(&cwd, &self.files_to_skip, &self.except_path, &self.exclude)
+    }
+
+    /// Where to read `--files-from` paths from, and what separates them
+    pub fn file_list(&self) -> io::Result<Option<(Box<dyn BufRead + Send>, u8)>> {
+        let separator = if self.null { b'\0' } else { b'\n' };
+        let reader: Box<dyn BufRead + Send> = match &self.files_from {
+            None => return Ok(None),
+            Some(p) if p.as_os_str() == STDIN_PATH => Box::new(BufReader::new(io::stdin())),
+            Some(p) => Box::new(BufReader::new(File::open(p)?)),
+        };
+        Ok(Some((reader, separator)))
+    }
+
+    /// 0 means pick for us, as it does for `WalkBuilder::threads`
+    pub fn thread_count(&self) -> usize {
+        self.threads
+            .filter(|&n| n > 0)
+            .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1)
     }
 
     pub fn walk_builder(&self) -> WalkBuilder {
-        let mut wb = self.base_walk_builder();
-        wb.follow_links(false)
+        match self.files.as_slice() {
+            [] => self.walk_builder_for(&[PathBuf::from(CURRENT_DIR)]),
+            files => self.walk_builder_for(files),
+        }
+    }
+
+    pub fn walk_builder_for(&self, roots: &[PathBuf]) -> WalkBuilder {
+        let mut wb = self.base_walk_builder(roots);
+        let no_ignore = self.no_ignore || self.unrestricted >= 1;
+        let no_ignore_vcs = no_ignore || self.no_ignore_vcs;
+        if !no_ignore {
+            wb.add_custom_ignore_filename(GSUBIGNORE_NAME);
+        }
+        wb.follow_links(self.follow_symlinks)
             .max_filesize(Some(self.max_file_size))
-            .hidden(!self.show_hidden_files);
+            .hidden(!self.show_hidden_files && self.unrestricted < 2)
+            .ignore(!no_ignore)
+            .git_ignore(!no_ignore_vcs)
+            .git_global(!no_ignore_vcs)
+            .git_exclude(!no_ignore_vcs)
+            .parents(!self.no_ignore_parent)
+            .max_depth(self.max_depth)
+            .same_file_system(self.one_file_system);
+        if let Some(n) = self.threads {
+            wb.threads(n);
+        }
+        if let Some(mirror) = self.mirror.clone() {
+            wb.filter_entry(move |e| !mirror.is_output(e.path()));
+        }
         wb
     }
 
-    fn base_walk_builder(&self) -> WalkBuilder {
-        let mut paths = self.files.iter();
+    fn base_walk_builder(&self, roots: &[PathBuf]) -> WalkBuilder {
+        let mut paths = roots.iter();
         let mut wb = WalkBuilder::new(paths
             .next()
             .unwrap_or(&PathBuf::from(CURRENT_DIR)));
         paths.for_each(|p| { wb.add(p); });
         wb
     }
-}
\ No newline at end of file
+}
+
+impl ApplyOpts {
+    pub fn presenter(&self) -> Presenter {
+        Presenter::new(max_level(self.quiet, self.verbose), self.format).color(self.color)
+    }
+
+    pub fn read_plan(&self) -> io::Result<Plan> {
+        if self.plan.as_os_str() == STDIN_PATH {
+            read_plan(io::stdin().lock())
+        } else {
+            read_plan(BufReader::new(File::open(&self.plan)?))
+        }
+    }
+
+    pub fn guard(&self) -> io::Result<Guard> {
+        protecting(&self.protect)
+    }
+
+    pub fn open_opts(&self) -> OpenOptions {
+        let mut open_opts = OpenOptions::new();
+        open_opts.read(true).write(!self.dry_run);
+        open_opts
+    }
+}
+
+impl ApplyEditsOpts {
+    pub fn presenter(&self) -> Presenter {
+        Presenter::new(max_level(self.quiet, self.verbose), self.format).color(self.color)
+    }
+
+    pub fn read_edits(&self) -> io::Result<Edits> {
+        let replacement = self.replacement.as_deref();
+        if self.edits.as_os_str() == STDIN_PATH {
+            read_edits(io::stdin().lock(), replacement)
+        } else {
+            read_edits(BufReader::new(File::open(&self.edits)?), replacement)
+        }
+    }
+
+    pub fn guard(&self) -> io::Result<Guard> {
+        protecting(&self.protect)
+    }
+
+    pub fn open_opts(&self) -> OpenOptions {
+        let mut open_opts = OpenOptions::new();
+        open_opts.read(true).write(!self.dry_run);
+        open_opts
+    }
+}
+
+impl CowOpts {
+    pub fn presenter(&self) -> Presenter {
+        Presenter::new(max_level(self.quiet, self.verbose), self.format).color(self.color)
+    }
+
+    pub fn cow_suffix(&self) -> &str {
+        self.cow_suffix.trim_start_matches('.')
+    }
+
+    pub fn guard(&self) -> io::Result<Guard> {
+        protecting(&self.protect)
+    }
+}
+
+/// Whether stdin is redirected from a pipe or a file, rather than a terminal or nothing at all
+#[cfg(unix)]
+fn stdin_is_piped() -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata("/dev/stdin")
+        .map(|md| md.file_type().is_fifo() || md.is_file())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn stdin_is_piped() -> bool {
+    use std::io::IsTerminal;
+    !io::stdin().is_terminal()
+}
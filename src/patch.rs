@@ -0,0 +1,144 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use similar::TextDiff;
+use crate::paths::relative_to;
+use crate::proposals::Proposals;
+use crate::replacer::{Replacement, Replacer};
+
+/// Lines of unchanged context around each hunk, same as `git diff`
+static CONTEXT_LINES: usize = 3;
+
+/// A git style unified diff of `path` going from `original` to `replaced`
+pub fn unified_diff(path: &Path, original: &str, replaced: &str) -> String {
+    let name = git_path(path);
+    let (a, b) = (quote(&[b"a/", &name[..]].concat()), quote(&[b"b/", &name[..]].concat()));
+    // git ends the file headers with a tab when a name has a space, so they can be told apart
+    // from a timestamp
+    let tab = if a.starts_with('"') || !name.contains(&b' ') { "" } else { "\t" };
+    let diff = TextDiff::from_lines(original, replaced);
+    format!(
+        "diff --git {} {}\n{}",
+        a,
+        b,
+        diff.unified_diff()
+            .context_radius(CONTEXT_LINES)
+            .header(&format!("{}{}", a, tab), &format!("{}{}", b, tab)),
+    )
+}
+
+/// `path` as git would write it: relative to the current directory where it's under it, with
+/// forward slashes and no leading `./`
+fn git_path(path: &Path) -> Vec<u8> {
+    let path = match env::current_dir() {
+        Ok(cwd) if path.has_root() => relative_to(&cwd, path),
+        _ => path.to_path_buf(),
+    };
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir | Component::RootDir | Component::Prefix(_)))
+        .map(|c| c.as_os_str().as_encoded_bytes())
+        .collect::<Vec<_>>()
+        .join(&b'/')
+}
+
+/// `name` C-quoted the way git does, if it has quotes, backslashes, control or non-ASCII
+/// characters in it
+fn quote(name: &[u8]) -> String {
+    let needs_quoting = |b: &u8| *b == b'"' || *b == b'\\' || *b < 0x20 || *b >= 0x7f;
+    if !name.iter().any(needs_quoting) {
+        return String::from_utf8_lossy(name).into_owned();
+    }
+    let mut quoted = String::from("\"");
+    for &b in name {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b if needs_quoting(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Collects the diff of every file gsub would change, for `--patch-out`
+#[derive(Default)]
+pub struct Patch {
+    diffs: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl Patch {
+    pub fn new() -> Self { Self::default() }
+}
+
+impl Proposals for Patch {
+    fn propose(&self, path: &Path, replacement: &Replacement, _: &Replacer) {
+        let diff = unified_diff(path, &replacement.original, &replacement.replaced);
+        self.diffs.lock().unwrap().push((path.to_path_buf(), diff));
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut diffs = self.diffs.lock().unwrap();
+        diffs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (_, diff) in diffs.iter() {
+            out.write_all(diff.as_bytes())?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_git_style_diffs() {
+        let diff = unified_diff(Path::new("./src/milk.txt"), "oat\nsoy\n", "oat\nalmond\n");
+        assert_eq!(diff, "\
+diff --git a/src/milk.txt b/src/milk.txt
+--- a/src/milk.txt
++++ b/src/milk.txt
+@@ -1,2 +1,2 @@
+ oat
+-soy
++almond
+");
+    }
+
+    #[test]
+    fn quotes_names_like_git() {
+        let header = |p: &str| unified_diff(Path::new(p), "a\n", "b\n").lines().take(3).collect::<Vec<_>>().join("\n");
+        assert_eq!(header("sp ace.txt"), "diff --git a/sp ace.txt b/sp ace.txt\n--- a/sp ace.txt\t\n+++ b/sp ace.txt\t");
+        assert_eq!(header("qu\"ote.txt"), "diff --git \"a/qu\\\"ote.txt\" \"b/qu\\\"ote.txt\"\n--- \"a/qu\\\"ote.txt\"\n+++ \"b/qu\\\"ote.txt\"");
+        assert_eq!(quote(b"a/tab\tx.txt"), "\"a/tab\\tx.txt\"");
+        assert_eq!(quote("a/ünï.txt".as_bytes()), "\"a/\\303\\274n\\303\\257.txt\"");
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(git_path(&cwd.join("src/milk.txt")), b"src/milk.txt");
+    }
+
+    #[test]
+    fn sorts_diffs_by_path() {
+        let replacer = Replacer::new("1", "2").unwrap();
+        let replacement = Replacement {
+            original: "1\n".to_string(),
+            replaced: "2\n".to_string(),
+            replacements: 1,
+            first_line: 1,
+        };
+        let patch = Patch::new();
+        patch.propose(Path::new("b"), &replacement, &replacer);
+        patch.propose(Path::new("a"), &replacement, &replacer);
+        let mut out = Vec::new();
+        patch.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.find("diff --git a/a").unwrap() < out.find("diff --git a/b").unwrap());
+    }
+}
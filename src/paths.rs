@@ -0,0 +1,65 @@
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// `path` made absolute against `cwd`, with `.` and `..` resolved without touching the disk
+pub fn absolute(cwd: &Path, path: &Path) -> PathBuf {
+    let mut abs = PathBuf::new();
+    for c in cwd.join(path).components() {
+        match c {
+            Component::CurDir => {},
+            Component::ParentDir => { abs.pop(); },
+            c => abs.push(c),
+        }
+    }
+    abs
+}
+
+/// `path` relative to `base` if it's under it, otherwise left as it is
+pub fn relative_to(base: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(base).map_or_else(|_| path.to_path_buf(), Path::to_path_buf)
+}
+
+/// `roots` without any that resolve to the same place as an earlier one, and the ones that
+/// were dropped, paired with the root they duplicate. Roots that can't be resolved are kept,
+/// so walking them reports why.
+pub fn dedupe_roots(roots: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<(PathBuf, PathBuf)>) {
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    let (mut kept, mut duplicates): (Vec<PathBuf>, _) = (Vec::new(), Vec::new());
+    for root in roots {
+        let resolved = fs::canonicalize(&root).unwrap_or_else(|_| root.clone());
+        match seen.get(&resolved) {
+            Some(&i) => duplicates.push((root, kept[i].clone())),
+            None => {
+                seen.insert(resolved, kept.len());
+                kept.push(root);
+            },
+        }
+    }
+    (kept, duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_dots_lexically() {
+        assert_eq!(absolute(Path::new("/repo"), Path::new("./src/../lib.rs")), PathBuf::from("/repo/lib.rs"));
+        assert_eq!(absolute(Path::new("/repo"), Path::new("/etc/hosts")), PathBuf::from("/etc/hosts"));
+        assert_eq!(relative_to(Path::new("/repo"), Path::new("/repo/lib.rs")), PathBuf::from("lib.rs"));
+        assert_eq!(relative_to(Path::new("/repo"), Path::new("/etc/hosts")), PathBuf::from("/etc/hosts"));
+    }
+
+    #[test]
+    fn dedupes_roots_that_resolve_to_the_same_place() {
+        let roots = ["src", "./src", "src/../src/", "missing", "missing"].map(PathBuf::from).to_vec();
+        let (kept, duplicates) = dedupe_roots(roots);
+        assert_eq!(kept, [PathBuf::from("src"), PathBuf::from("missing")]);
+        assert_eq!(duplicates, [
+            (PathBuf::from("./src"), PathBuf::from("src")),
+            (PathBuf::from("src/../src/"), PathBuf::from("src")),
+            (PathBuf::from("missing"), PathBuf::from("missing")),
+        ]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::event::{GsubEvent, SkipReason};
+use crate::file_data::{OpenFileData, OverWrite};
+use crate::proposals::Proposals;
+use crate::replacer::{Expansion, Replacement, Replacer};
+
+static PLAN_VERSION: u32 = 1;
+/// Hex digits of the hash kept in an `Edit`'s ID
+static ID_LEN: usize = 12;
+
+/// Every edit `gsub plan` proposes, for `gsub apply` to make later. Reviewers can delete
+/// edits (or whole files) in between, but anything else they change makes the edit invalid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub pattern: String,
+    pub replacement: String,
+    pub files: Vec<FilePlan>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePlan {
+    pub path: PathBuf,
+    /// Of the file's contents when the plan was made
+    pub sha256: String,
+    pub edits: Vec<Edit>,
+}
+
+/// Replaces `old`, found at the byte offsets `start..end`, with `new`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    /// Derived from the path and the edit itself, so planning the same run twice gives the same IDs
+    pub id: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub old: String,
+    pub new: String,
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+impl Edit {
+    fn new(path: &Path, contents: &str, expansion: Expansion) -> Self {
+        let Expansion { range, line, text } = expansion;
+        let old = contents[range.clone()].to_string();
+        let key = format!("{}\0{}\0{}\0{}", path.display(), range.start, old, text);
+        let mut id = sha256(key.as_bytes());
+        id.truncate(ID_LEN);
+        Edit { id, line, start: range.start, end: range.end, old, new: text }
+    }
+}
+
+impl FilePlan {
+    pub fn new(path: &Path, contents: &str, replacer: &Replacer) -> Self {
+        let edits = replacer.expansions(contents)
+            .into_iter()
+            .map(|e| Edit::new(path, contents, e))
+            .collect();
+        FilePlan { path: path.to_path_buf(), sha256: sha256(contents.as_bytes()), edits }
+    }
+
+    /// `contents` with every edit made, as long as it's what the plan was made against
+    pub fn apply_to(&self, contents: &[u8]) -> Result<Vec<u8>, SkipReason> {
+        if sha256(contents) != self.sha256 {
+            return Err(SkipReason::ChangedSincePlan);
+        }
+        let mut edits = self.edits.iter().collect::<Vec<_>>();
+        edits.sort_by_key(|e| e.start);
+        let mut applied = Vec::with_capacity(contents.len());
+        let mut last_end = 0;
+        for e in edits {
+            let lines_up = last_end <= e.start
+                && contents.get(e.start..e.end) == Some(e.old.as_bytes());
+            if !lines_up {
+                return Err(SkipReason::InvalidPlan);
+            }
+            applied.extend_from_slice(&contents[last_end..e.start]);
+            applied.extend_from_slice(e.new.as_bytes());
+            last_end = e.end;
+        }
+        applied.extend_from_slice(&contents[last_end..]);
+        Ok(applied)
+    }
+}
+
+/// Reads a plan written by `gsub plan`
+pub fn read_plan(reader: impl Read) -> io::Result<Plan> {
+    let plan: Plan = serde_json::from_reader(reader)?;
+    if plan.version != PLAN_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported plan version {}", plan.version),
+        ));
+    }
+    Ok(plan)
+}
+
+/// Makes the edits planned for one file, refusing to if it's changed since
+pub fn apply_file(
+    file: &FilePlan,
+    opener: &impl OpenFileData,
+    lock: bool,
+    dry_run: bool,
+) -> Result<GsubEvent, GsubEvent>
+{
+    let path = file.path.clone();
+    let skipped = |reason| GsubEvent::Skipped { path: file.path.clone(), reason };
+    let mut fd = opener.open_path(&path).map_err(|e| skipped(SkipReason::OpenFailed(e.kind())))?;
+    if lock {
+        fd.lock().map_err(|e| skipped(SkipReason::LockFailed(e.kind())))?;
+    }
+    let mut contents = Vec::new();
+    fd.read_to_end(&mut contents).map_err(|e| skipped(SkipReason::from_read_error(&e)))?;
+    let applied = file.apply_to(&contents).map_err(skipped)?;
+    let (replacements, line) = match file.edits.iter().map(|e| e.line).min() {
+        Some(line) => (file.edits.len(), line),
+        None => return Ok(GsubEvent::Unchanged { path }),
+    };
+    if dry_run {
+        return Ok(GsubEvent::WouldUpdate { path, replacements, line });
+    }
+    if !fd.unmodified_since_open().unwrap_or(false) {
+        return Err(skipped(SkipReason::ModifiedConcurrently));
+    }
+    fd.overwrite(&applied)
+        .map_err(|e| GsubEvent::WriteFailed { path: path.clone(), kind: e.kind() })?;
+    Ok(GsubEvent::Updated { path, replacements, line })
+}
+
+/// Collects a `FilePlan` for every file `gsub plan` would change
+pub struct Planner {
+    pattern: String,
+    replacement: String,
+    files: Mutex<Vec<FilePlan>>,
+}
+
+impl Planner {
+    pub fn new(pattern: &str, replacement: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            files: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Proposals for Planner {
+    fn propose(&self, path: &Path, replacement: &Replacement, replacer: &Replacer) {
+        let file = FilePlan::new(path, &replacement.original, replacer);
+        self.files.lock().unwrap().push(file);
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap().clone();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let plan = Plan {
+            version: PLAN_VERSION,
+            pattern: self.pattern.clone(),
+            replacement: self.replacement.clone(),
+            files,
+        };
+        serde_json::to_writer_pretty(&mut *out, &plan)?;
+        writeln!(out)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn milk_plan() -> FilePlan {
+        let replacer = Replacer::new(r"(\w+) milk", "${1}ly milk").unwrap();
+        FilePlan::new(Path::new("milk.txt"), "oat milk\nsoy milk\n", &replacer)
+    }
+
+    #[test]
+    fn plans_edits_with_offsets_and_stable_ids() {
+        let plan = milk_plan();
+        assert_eq!(plan.edits.len(), 2);
+        assert_eq!(
+            (plan.edits[1].line, plan.edits[1].start, plan.edits[1].end),
+            (2, 9, 17)
+        );
+        assert_eq!(plan.edits[1].old, "soy milk");
+        assert_eq!(plan.edits[1].new, "soyly milk");
+        assert_eq!(plan.edits[0].id.len(), ID_LEN);
+        assert_eq!(plan.edits, milk_plan().edits);
+    }
+
+    #[test]
+    fn applies_only_the_edits_left_in_the_plan() {
+        let mut plan = milk_plan();
+        plan.edits.remove(0);
+        let applied = plan.apply_to(b"oat milk\nsoy milk\n").unwrap();
+        assert_eq!(applied, b"oat milk\nsoyly milk\n");
+    }
+
+    #[test]
+    fn refuses_files_that_changed_since_planning() {
+        let plan = milk_plan();
+        assert_eq!(plan.apply_to(b"oat milk\nrice milk\n"), Err(SkipReason::ChangedSincePlan));
+    }
+
+    #[test]
+    fn refuses_edits_that_dont_line_up() {
+        let mut plan = milk_plan();
+        plan.edits[0].old = "rice milk".to_string();
+        assert_eq!(plan.apply_to(b"oat milk\nsoy milk\n"), Err(SkipReason::InvalidPlan));
+    }
+}
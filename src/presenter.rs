@@ -1,32 +1,234 @@
-use std::{fmt, io};
+use std::io::{self, IsTerminal, Write};
+use std::fs::File;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::event::{GsubEvent, Level};
+use crate::progress::CLEAR_LINE;
+use crate::color::{ColorChoice, Painter};
 
-pub enum Msg<M> {
-    Important(M),
-    Verbose(M),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    LspWorkspaceEdit,
+    Quickfix,
 }
 
-pub trait ToMsg: fmt::Display + Sized  {
-    fn important(self) -> Msg<String> { Msg::Important(self.to_string()) }
-    fn verbose(self) -> Msg<String> { Msg::Verbose(self.to_string()) }
+impl Format {
+    pub const VARIANTS: &'static [&'static str] = &["text", "json", "lsp-workspace-edit", "quickfix"];
+    /// The formats that print events, rather than the changes themselves
+    pub const EVENT_VARIANTS: &'static [&'static str] = &["text", "json"];
+
+    /// Whether this prints the changes for an editor to make, instead of what gsub did
+    pub fn proposes(self) -> bool {
+        matches!(self, Format::LspWorkspaceEdit | Format::Quickfix)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "lsp-workspace-edit" => Ok(Format::LspWorkspaceEdit),
+            "quickfix" => Ok(Format::Quickfix),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
+
+/// Orders in which results can be printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Path,
+}
+
+impl SortBy {
+    pub const VARIANTS: &'static [&'static str] = &["path"];
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(SortBy::Path),
+            _ => Err(format!("can't sort by '{}'", s)),
+        }
+    }
+}
+
+/// Running totals for `--summary-only`
+#[derive(Default)]
+struct Tally {
+    changed: AtomicUsize,
+    replacements: AtomicUsize,
+    unchanged: AtomicUsize,
+    skipped: AtomicUsize,
+    failed: AtomicUsize,
 }
 
-impl ToMsg for io::Error {}
-impl ToMsg for String {}
+impl Tally {
+    fn count(&self, event: &GsubEvent) {
+        use GsubEvent::*;
+        let counter = match event {
+            Updated { replacements, .. }
+            | WouldUpdate { replacements, .. }
+            | Created { replacements, .. } => {
+                self.replacements.fetch_add(*replacements, Ordering::Relaxed);
+                &self.changed
+            },
+            Accepted { .. } | Discarded { .. } => &self.changed,
+            Unchanged { .. } | Copied { .. } => &self.unchanged,
+            Skipped { .. } if event.level() == Level::Error => &self.failed,
+            Skipped { .. } | Duplicate { .. } => &self.skipped,
+            WriteFailed { .. } => &self.failed,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> GsubEvent {
+        GsubEvent::Summary {
+            changed: self.changed.load(Ordering::Relaxed),
+            replacements: self.replacements.load(Ordering::Relaxed),
+            unchanged: self.unchanged.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
 
 pub struct Presenter {
-    verbose: bool,
+    max_level: Level,
+    format: Format,
+    summary_only: bool,
+    /// Set when stdout is reserved for machine readable output, e.g. `--print0`
+    stdout_reserved: bool,
+    /// Set when a progress display is drawn to stderr, which we need to erase before printing
+    under_progress: bool,
+    /// Gets every event, regardless of `max_level`
+    log_file: Option<Mutex<File>>,
+    stdout_painter: Painter,
+    stderr_painter: Painter,
+    /// With `--sort`, events about files are held here until `flush_sorted()`
+    sorted: Option<Mutex<Vec<GsubEvent>>>,
+    tally: Tally,
 }
 
 impl Presenter {
-    pub fn new(verbose: bool) -> Self {
-        Self { verbose: verbose }
+    pub fn new(max_level: Level, format: Format) -> Self {
+        Self {
+            max_level,
+            format,
+            summary_only: false,
+            stdout_reserved: false,
+            under_progress: false,
+            log_file: None,
+            sorted: None,
+            stdout_painter: Painter::plain(),
+            stderr_painter: Painter::plain(),
+            tally: Tally::default(),
+        }
+    }
+
+    pub fn summary_only(mut self, yes: bool) -> Self {
+        self.summary_only = yes;
+        self
+    }
+
+    pub fn stdout_reserved(mut self, yes: bool) -> Self {
+        self.stdout_reserved = yes;
+        self
     }
 
-    pub fn wax<M>(&self, msg: Msg<M>) where M: fmt::Display {
-        match (msg, self.verbose) {
-            (Msg::Important(m), _) => println!("{}", m),
-            (Msg::Verbose(m), true) => eprintln!("{}", m),
-            _ => {}
+    pub fn under_progress(mut self, yes: bool) -> Self {
+        self.under_progress = yes;
+        self
+    }
+
+    pub fn sort_by(mut self, sort: Option<SortBy>) -> Self {
+        self.sorted = sort.map(|SortBy::Path| Mutex::new(Vec::new()));
+        self
+    }
+
+    pub fn color(mut self, choice: ColorChoice) -> Self {
+        self.stdout_painter = Painter::new(choice.enabled(io::stdout().is_terminal()));
+        self.stderr_painter = Painter::new(choice.enabled(io::stderr().is_terminal()));
+        self
+    }
+
+    pub fn log_to(mut self, file: File) -> Self {
+        self.log_file = Some(Mutex::new(file));
+        self
+    }
+
+    pub fn wax(&self, event: GsubEvent) {
+        match &self.sorted {
+            Some(buffer) if event.path().is_some() => buffer.lock().unwrap().push(event),
+            _ => self.present(event),
+        }
+    }
+
+    /// Prints everything held back by `--sort`, in order
+    pub fn flush_sorted(&self) {
+        let mut events = match &self.sorted {
+            Some(buffer) => std::mem::take(&mut *buffer.lock().unwrap()),
+            None => return,
+        };
+        events.sort_by(|a, b| a.path().cmp(&b.path()));
+        events.into_iter().for_each(|e| self.present(e));
+    }
+
+    /// Prints the summary, if one was asked for
+    pub fn finish(&self) {
+        self.flush_sorted();
+        let summary = self.tally.summary();
+        if let Some(log) = &self.log_file {
+            let _ = writeln!(log.lock().unwrap(), "{}", self.render(&summary));
+        }
+        if self.summary_only {
+            self.print(Level::Important, &summary);
         }
     }
-}
\ No newline at end of file
+
+    fn present(&self, event: GsubEvent) {
+        self.tally.count(&event);
+        if let Some(log) = &self.log_file {
+            // Losing a log line isn't worth aborting the run over
+            let _ = writeln!(log.lock().unwrap(), "{}", self.render(&event));
+        }
+        let level = event.level();
+        let hidden_by_summary = self.summary_only && level > Level::Error;
+        if level > self.max_level || hidden_by_summary {
+            return;
+        }
+        self.print(level, &event);
+    }
+
+    fn print(&self, level: Level, event: &GsubEvent) {
+        if self.under_progress {
+            eprint!("{}", CLEAR_LINE);
+        }
+        if level == Level::Important && !self.stdout_reserved {
+            println!("{}", self.render_with(event, &self.stdout_painter))
+        } else {
+            eprintln!("{}", self.render_with(event, &self.stderr_painter))
+        }
+    }
+
+    fn render(&self, event: &GsubEvent) -> String {
+        self.render_with(event, &Painter::plain())
+    }
+
+    fn render_with(&self, event: &GsubEvent, painter: &Painter) -> String {
+        match self.format {
+            // The editor formats write the changes to stdout, so events are just for people
+            Format::Text | Format::LspWorkspaceEdit | Format::Quickfix => event.paint(painter),
+            Format::Json => serde_json::to_string(event).expect("events always serialize"),
+        }
+    }
+}
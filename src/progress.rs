@@ -0,0 +1,154 @@
+use std::io::{self, IsTerminal, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+static REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Erases whatever the progress display last drew
+pub static CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Counters shared by the walker threads, drawn to stderr as a one line progress display.
+/// Everything is a no-op when it's disabled, so callers don't need to check.
+pub struct Progress {
+    enabled: bool,
+    visited: AtomicUsize,
+    changed: AtomicUsize,
+    bytes: AtomicU64,
+    /// Files found by the pre-count pass, which runs alongside the real walk
+    total: AtomicUsize,
+    counted: AtomicBool,
+    /// Cleared by `no_count()` when there's no pre-count pass to wait for
+    counting: AtomicBool,
+    done: AtomicBool,
+    start: Instant,
+}
+
+impl Progress {
+    /// Only enables itself when stderr is a terminal, so logs and pipes stay clean
+    pub fn new(wanted: bool) -> Self {
+        Self {
+            enabled: wanted && io::stderr().is_terminal(),
+            visited: AtomicUsize::new(0),
+            changed: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            total: AtomicUsize::new(0),
+            counted: AtomicBool::new(false),
+            counting: AtomicBool::new(true),
+            done: AtomicBool::new(false),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool { self.enabled }
+
+    pub fn visit(&self, bytes: u64) {
+        if self.enabled {
+            self.visited.fetch_add(1, Ordering::Relaxed);
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn change(&self) {
+        if self.enabled {
+            self.changed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// For walks that won't be counted, e.g. of paths read from `--files-from`
+    pub fn no_count(&self) {
+        self.counting.store(false, Ordering::Relaxed);
+    }
+
+    /// Adds a file found by the pre-count pass. Returns false once the real walk has beaten
+    /// it, since there's no point counting any further.
+    pub fn count_file(&self) -> bool {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        !self.done.load(Ordering::Relaxed)
+    }
+
+    /// The pre-count pass found every file, so there's a total to estimate from
+    pub fn counted_all(&self) {
+        self.counted.store(true, Ordering::Relaxed);
+    }
+
+    /// Redraws the progress line until `finish()` is called
+    pub fn draw_until_finished(&self) {
+        if !self.enabled {
+            return;
+        }
+        while !self.done.load(Ordering::Relaxed) {
+            eprint!("{}{}", CLEAR_LINE, self.status_line(self.start.elapsed()));
+            let _ = io::stderr().flush();
+            thread::sleep(REDRAW_INTERVAL);
+        }
+        eprint!("{}", CLEAR_LINE);
+    }
+
+    pub fn finish(&self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+
+    fn status_line(&self, elapsed: Duration) -> String {
+        let visited = self.visited.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let files = if self.counted.load(Ordering::Relaxed) {
+            let total = self.total.load(Ordering::Relaxed).max(visited);
+            let rate = visited as f64 / secs;
+            let eta = if rate > 0.0 { ((total - visited) as f64 / rate).ceil() } else { 0.0 };
+            format!("{}/{} files, ETA {}s", visited, total, eta)
+        } else if self.counting.load(Ordering::Relaxed) {
+            format!("{} files (counting...)", visited)
+        } else {
+            format!("{} files", visited)
+        };
+        format!(
+            "{}, {} changed, {} ({}/s)",
+            files,
+            self.changed.load(Ordering::Relaxed),
+            human_bytes(bytes),
+            human_bytes((bytes as f64 / secs) as u64),
+        )
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_bytes_for_humans() {
+        assert_eq!(human_bytes(12), "12 B");
+        assert_eq!(human_bytes(2048), "2.0 KiB");
+        assert_eq!(human_bytes(5 * 1024 * 1024 + 512 * 1024), "5.5 MiB");
+    }
+
+    #[test]
+    fn estimates_time_remaining_once_files_are_counted() {
+        let p = Progress { enabled: true, ..Progress::new(false) };
+        p.visit(1024);
+        p.visit(1024);
+        p.change();
+        assert_eq!(p.status_line(Duration::from_secs(2)), "2 files (counting...), 1 changed, 2.0 KiB (1.0 KiB/s)");
+
+        p.total.store(6, Ordering::Relaxed);
+        p.counted.store(true, Ordering::Relaxed);
+        assert_eq!(p.status_line(Duration::from_secs(2)), "2/6 files, ETA 4s, 1 changed, 2.0 KiB (1.0 KiB/s)");
+    }
+}
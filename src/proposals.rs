@@ -0,0 +1,12 @@
+use std::io::{self, Write};
+use std::path::Path;
+use crate::replacer::{Replacement, Replacer};
+
+/// Where `gsub()` sends the changes it's only proposing, like a `--patch-out` patch or a
+/// `gsub plan`
+pub trait Proposals: Sync {
+    fn propose(&self, path: &Path, replacement: &Replacement, replacer: &Replacer);
+
+    /// Writes every proposal, sorted by path so the output doesn't depend on the walk order
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()>;
+}
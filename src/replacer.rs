@@ -1,8 +1,36 @@
 use std::io;
-use std::borrow::Cow::{Borrowed, Owned};
+use std::borrow::Cow;
+use std::ops::Range;
 use regex::{self, Regex};
+use serde::Serialize;
 use crate::file_data::SizedReader;
 
+/// A file's contents before and after a replacement was made
+#[derive(Debug)]
+pub struct Replacement {
+    pub original: String,
+    pub replaced: String,
+    pub replacements: usize,
+    /// 1-based line number of the first replacement
+    pub first_line: usize,
+}
+
+/// What the replacement expanded to for a single match, for `--only-output`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Extraction {
+    pub line: usize,
+    pub text: String,
+}
+
+/// A single match, and what the replacement expands to there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    /// Byte offsets of the match
+    pub range: Range<usize>,
+    pub line: usize,
+    pub text: String,
+}
+
 #[derive(Debug)]
 pub struct Replacer<'a> {
     pattern: Regex,
@@ -15,13 +43,57 @@ impl<'a> Replacer<'a> {
         Ok(Replacer { pattern, replacement, })
     }
 
-    pub fn replace(&self, fd: &mut impl SizedReader) -> io::Result<Option<String>> {
-        let mut buffer = String::with_capacity(fd.byte_size());
-        fd.read_to_string(&mut buffer)?;
-        Ok(match self.pattern.replace_all(&buffer, self.replacement) {
-            Borrowed(_) => None,
-            Owned(s) => Some(s),
-        })
+    pub fn pattern(&self) -> &Regex { &self.pattern }
+
+    pub fn replace_all<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        self.pattern.replace_all(text, self.replacement)
+    }
+
+    pub fn replace(&self, fd: &mut impl SizedReader) -> io::Result<Option<Replacement>> {
+        let original = fd.read_all()?;
+        let mut replaced = String::with_capacity(original.len());
+        let mut replacements = 0;
+        let mut last_match_end = 0;
+        let mut first_line = 1;
+        for caps in self.pattern.captures_iter(&original) {
+            let m = caps.get(0).expect("capture group 0 is always the whole match");
+            if replacements == 0 {
+                first_line += original[..m.start()].matches('\n').count();
+            }
+            replaced.push_str(&original[last_match_end..m.start()]);
+            caps.expand(self.replacement, &mut replaced);
+            last_match_end = m.end();
+            replacements += 1;
+        }
+        if replacements == 0 {
+            return Ok(None);
+        }
+        replaced.push_str(&original[last_match_end..]);
+        Ok(Some(Replacement { original, replaced, replacements, first_line }))
+    }
+
+    /// The replacement expanded for every match in `haystack`, without the surrounding text
+    pub fn extract(&self, haystack: &str) -> Vec<Extraction> {
+        self.expansions(haystack)
+            .into_iter()
+            .map(|e| Extraction { line: e.line, text: e.text })
+            .collect()
+    }
+
+    /// Where each match in `haystack` is, and what the replacement expands to there
+    pub fn expansions(&self, haystack: &str) -> Vec<Expansion> {
+        let mut line = 1;
+        let mut counted_up_to = 0;
+        self.pattern.captures_iter(haystack)
+            .map(|caps| {
+                let m = caps.get(0).expect("capture group 0 is always the whole match");
+                line += haystack[counted_up_to..m.start()].matches('\n').count();
+                counted_up_to = m.start();
+                let mut text = String::new();
+                caps.expand(self.replacement, &mut text);
+                Expansion { range: m.range(), line, text }
+            })
+            .collect()
     }
 }
 
@@ -51,8 +123,9 @@ error: repetition operator missing expression";
         let replaced = r.replace(&mut file)
             .expect("'Spongebob' should've been replaced with 'Squidward'")
             .unwrap();
+        assert_eq!(replaced.first_line, 2);
         assert_eq!(
-            &replaced,
+            &replaced.replaced,
             "Who lives in an Easter-Island Head under the sea?\nSquidward Tentacles!"
         );
     }
@@ -90,8 +163,29 @@ baz()
 foo_and_bar()
 gabagool()\
         ".to_string();
-        let dryed_code = r.replace(&mut wet_code).expect("Unable to dedup");
-        assert_eq!(dryed_code.unwrap(), expected_dry_code);
+        let dryed_code = r.replace(&mut wet_code).expect("Unable to dedup").unwrap();
+        assert_eq!(dryed_code.replaced, expected_dry_code);
+        assert_eq!(dryed_code.replacements, 2);
+        assert_eq!(dryed_code.first_line, 1);
+    }
+
+    #[test]
+    fn expands_capture_groups_in_the_replacement() {
+        let r = Replacer::new(r"(\w+) milk", "milk of the ${1}").unwrap();
+        let mut file = MockFileData::new("oat milk and soy milk");
+        let replaced = r.replace(&mut file).unwrap().unwrap();
+        assert_eq!(replaced.replaced, "milk of the oat and milk of the soy");
+        assert_eq!(replaced.replacements, 2);
+    }
+
+    #[test]
+    fn extracts_expanded_replacements_for_each_match() {
+        let r = Replacer::new(r#"(\w+) = "(.*)""#, "$1,$2").unwrap();
+        let extracted = r.extract("[package]\nname = \"gsub\"\nversion = \"0.1.0\"\n");
+        assert_eq!(extracted, vec![
+            Extraction { line: 2, text: "name,gsub".to_string() },
+            Extraction { line: 3, text: "version,0.1.0".to_string() },
+        ]);
     }
 
     #[test]
@@ -101,7 +195,7 @@ gabagool()\
         let r = Replacer::new("capicola", "gabagool").unwrap();
         let f1_new = r.replace(&mut f1).unwrap();
         let f2_new = r.replace(&mut f2).unwrap();
-        assert_eq!(f1_new.unwrap(), "gabagool isn't vegan");
-        assert_eq!(f2_new.unwrap(), "gabagool is gluten free");
+        assert_eq!(f1_new.unwrap().replaced, "gabagool isn't vegan");
+        assert_eq!(f2_new.unwrap().replaced, "gabagool is gluten free");
     }
 }
\ No newline at end of file
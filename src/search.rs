@@ -0,0 +1,162 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use ignore::DirEntry;
+use regex::Regex;
+use serde::Serialize;
+use crate::opts::Opts;
+use crate::replacer::Replacer;
+use crate::event::{GsubEvent, SkipReason};
+use crate::file_data::{OpenFileData, SizedReader};
+
+/// A line printed by `--search`. Lines without a `column` are only there for context.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchLine {
+    pub line: usize,
+    pub column: Option<usize>,
+    pub text: String,
+    /// Byte ranges of `text` that matched
+    pub matches: Vec<Range<usize>>,
+}
+
+/// Like `gsub()`, but only reports what the pattern matches (or what it would be replaced with)
+pub fn search(
+    entry: DirEntry,
+    opener: &impl OpenFileData,
+    replacer: &Replacer,
+    opts: &Opts,
+) -> Result<GsubEvent, GsubEvent>
+{
+    let skipped = |path: &Path, reason| GsubEvent::Skipped { path: path.to_path_buf(), reason };
+    let path = entry.path().to_path_buf();
+    let mut fd = opener.open_fd(entry)
+        .map_err(|e| skipped(&path, SkipReason::OpenFailed(e.kind())))?;
+    let contents = fd.read_all()
+        .map_err(|e| skipped(&path, SkipReason::from_read_error(&e)))?;
+    Ok(search_contents(path, &contents, replacer, opts))
+}
+
+/// What `search()` reports for `contents`, however we got hold of them
+pub fn search_contents(path: PathBuf, contents: &str, replacer: &Replacer, opts: &Opts) -> GsubEvent {
+    let pattern = replacer.pattern();
+    if !pattern.is_match(contents) {
+        return GsubEvent::Unchanged { path };
+    }
+    if opts.only_output {
+        GsubEvent::Extracted { path, matches: replacer.extract(contents) }
+    } else if opts.files_with_matches {
+        GsubEvent::FileMatched { path }
+    } else if opts.count {
+        GsubEvent::Counted { path, count: pattern.find_iter(contents).count() }
+    } else {
+        GsubEvent::Found { path, lines: search_lines(pattern, contents, opts.context) }
+    }
+}
+
+/// Every line with a match in `haystack`, plus `context` lines on either side
+pub fn search_lines(pattern: &Regex, haystack: &str, context: usize) -> Vec<SearchLine> {
+    let mut line_starts = vec![0];
+    line_starts.extend(haystack.match_indices('\n').map(|(i, _)| i + 1));
+    if line_starts.len() > 1 && line_starts.last() == Some(&haystack.len()) {
+        // A trailing newline ends the last line, it doesn't start a new one
+        line_starts.pop();
+    }
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+    let line_text = |i: usize| {
+        let end = line_starts.get(i + 1).map_or(haystack.len(), |&next| next - 1);
+        let text = &haystack[line_starts[i]..end];
+        text.strip_suffix('\n').unwrap_or(text)
+    };
+
+    // Matches can span several lines, so split each one up by the lines it touches
+    let mut matches: Vec<(usize, Range<usize>)> = Vec::new();
+    for m in pattern.find_iter(haystack) {
+        let (first, last) = (line_of(m.start()), line_of(m.end().saturating_sub(1).max(m.start())));
+        for (i, &line_start) in line_starts.iter().enumerate().take(last + 1).skip(first) {
+            // A match right at the end of the file falls after the trailing newline
+            let len = line_text(i).len();
+            let start = (m.start().max(line_start) - line_start).min(len);
+            let end = (m.end() - line_start).min(len).max(start);
+            matches.push((i, start..end));
+        }
+    }
+
+    let mut lines: Vec<SearchLine> = Vec::new();
+    for (i, range) in matches {
+        let wanted = i.saturating_sub(context)..=(i + context).min(line_starts.len() - 1);
+        for j in wanted {
+            let is_match = j == i;
+            // Matches come in order, so a line we've already added is near the end
+            let existing = lines.iter_mut().rev()
+                .take_while(|l| l.line > j)
+                .find(|l| l.line == j + 1);
+            if let Some(l) = existing {
+                if is_match {
+                    l.column.get_or_insert(range.start + 1);
+                    l.matches.push(range.clone());
+                }
+                continue;
+            }
+            lines.push(SearchLine {
+                line: j + 1,
+                column: if is_match { Some(range.start + 1) } else { None },
+                text: line_text(j).to_string(),
+                matches: if is_match { vec![range.clone()] } else { Vec::new() },
+            });
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_lines_and_columns_of_matches() {
+        let pattern = Regex::new("wood").unwrap();
+        let lines = search_lines(&pattern, "How much\nwood could a wood\nchuck chuck", 0);
+        assert_eq!(lines, vec![SearchLine {
+            line: 2,
+            column: Some(1),
+            text: "wood could a wood".to_string(),
+            matches: vec![0..4, 13..17],
+        }]);
+    }
+
+    #[test]
+    fn includes_context_lines_without_duplicates() {
+        let pattern = Regex::new("b|c").unwrap();
+        let lines = search_lines(&pattern, "a\nb\nc\nd\ne", 1);
+        let summary = lines.iter().map(|l| (l.line, l.column)).collect::<Vec<_>>();
+        assert_eq!(summary, vec![(1, None), (2, Some(1)), (3, Some(1)), (4, None)]);
+    }
+
+    #[test]
+    fn adds_later_matches_to_lines_already_shown_as_context() {
+        let pattern = Regex::new("x").unwrap();
+        let lines = search_lines(&pattern, "x x\ny", 1);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].matches, vec![0..1, 2..3]);
+        assert_eq!(lines[1].column, None);
+    }
+
+    #[test]
+    fn splits_multiline_matches_by_line() {
+        let pattern = Regex::new(r"foo\(\)\nbar").unwrap();
+        let lines = search_lines(&pattern, "foo()\nbar()\n", 0);
+        let summary = lines.iter()
+            .flat_map(|l| l.matches.iter().map(move |m| (l.line, m.start, m.end)))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![(1, 0, 5), (2, 0, 3)]);
+    }
+
+    #[test]
+    fn puts_empty_matches_at_the_end_of_the_file_on_the_last_line() {
+        for pattern in ["$", "(?m)$"] {
+            let lines = search_lines(&Regex::new(pattern).unwrap(), "a\n", 0);
+            assert_eq!(lines.len(), 1);
+            assert_eq!((lines[0].line, lines[0].column, lines[0].text.as_str()), (1, Some(2), "a"));
+            assert!(lines[0].matches.iter().all(|m| *m == (1..1)));
+        }
+    }
+}
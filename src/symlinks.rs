@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use ignore::DirEntry;
+
+/// What `--follow-symlinks` does with a symlink that points outside the files/directories given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutsideLinks {
+    Skip,
+    Follow,
+    /// Skip it, reporting it as an error
+    Error,
+}
+
+impl OutsideLinks {
+    pub const VARIANTS: &'static [&'static str] = &["skip", "follow", "error"];
+}
+
+impl FromStr for OutsideLinks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OutsideLinks::Skip),
+            "follow" => Ok(OutsideLinks::Follow),
+            "error" => Ok(OutsideLinks::Error),
+            _ => Err(format!("unknown symlink policy '{}'", s)),
+        }
+    }
+}
+
+/// What to do with an entry the walker found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkAction {
+    Visit,
+    /// It's a symlink, and we aren't following it
+    Ignore,
+    /// It's a symlink given by name, and `--follow-named-symlinks` isn't set
+    IgnoreNamed,
+    /// It leads outside the roots, and `--outside-symlinks` is `skip`
+    Skip,
+    /// It leads outside the roots, and `--outside-symlinks` is `error`
+    Refuse,
+}
+
+/// Decides which symlinks get followed. The walker itself takes care of loops.
+#[derive(Debug)]
+pub struct Symlinks {
+    follow: bool,
+    follow_named: bool,
+    outside: OutsideLinks,
+    /// Where the roots really are, to tell whether a symlink leads outside them
+    roots: Vec<PathBuf>,
+}
+
+impl Symlinks {
+    pub fn new(follow: bool, follow_named: bool, outside: OutsideLinks, roots: &[PathBuf]) -> Self {
+        let roots = roots.iter().filter_map(|r| fs::canonicalize(r).ok()).collect();
+        Self { follow, follow_named, outside, roots }
+    }
+
+    pub fn check(&self, entry: &DirEntry) -> LinkAction {
+        // The walker always resolves the roots themselves, so ask the filesystem about those
+        let named = entry.depth() == 0;
+        let is_link = if named {
+            fs::symlink_metadata(entry.path()).is_ok_and(|md| md.file_type().is_symlink())
+        } else {
+            entry.path_is_symlink()
+        };
+        if !is_link {
+            return LinkAction::Visit;
+        }
+        if named {
+            // Walking into a named directory isn't editing the link, so that's always fine
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            return if is_dir || self.follow || self.follow_named { LinkAction::Visit } else { LinkAction::IgnoreNamed };
+        }
+        if !self.follow {
+            return LinkAction::Ignore;
+        }
+        match self.outside {
+            _ if self.inside(entry.path()) => LinkAction::Visit,
+            OutsideLinks::Follow => LinkAction::Visit,
+            OutsideLinks::Skip => LinkAction::Skip,
+            OutsideLinks::Error => LinkAction::Refuse,
+        }
+    }
+
+    /// Whether `path` resolves to somewhere under one of the roots
+    fn inside(&self, path: &Path) -> bool {
+        fs::canonicalize(path).is_ok_and(|p| self.roots.iter().any(|r| p.starts_with(r)))
+    }
+}
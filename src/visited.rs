@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::metadata::file_id;
+
+/// Every file visited so far, so a file reachable by more than one path (overlapping roots,
+/// hard links) is only ever edited once.
+#[derive(Debug, Default)]
+pub struct Visited {
+    /// (device, inode) -> the path the file was first visited by
+    files: Mutex<HashMap<(u64, u64), PathBuf>>,
+}
+
+impl Visited {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks `path` visited, returning the path it was first visited by if it already was
+    pub fn visit(&self, path: &Path, md: &Metadata) -> Option<PathBuf> {
+        // Without a stable file ID, every path counts as a different file
+        let id = file_id(md)?;
+        let mut files = self.files.lock().unwrap();
+        match files.get(&id) {
+            Some(first) => Some(first.clone()),
+            None => {
+                files.insert(id, path.to_path_buf());
+                None
+            },
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn recognizes_hard_links_as_the_same_file() {
+        fs::create_dir_all("test-files/visited").unwrap();
+        fs::write("test-files/visited/milk.txt", "oat milk").unwrap();
+        fs::hard_link("test-files/visited/milk.txt", "test-files/visited/link.txt").unwrap();
+        let md = |p| fs::metadata(p).unwrap();
+
+        let visited = Visited::new();
+        let first = visited.visit(Path::new("test-files/visited/milk.txt"), &md("test-files/visited/milk.txt"));
+        let again = visited.visit(Path::new("test-files/visited/link.txt"), &md("test-files/visited/link.txt"));
+        fs::remove_dir_all("test-files/visited").unwrap();
+
+        assert_eq!(first, None);
+        assert_eq!(again, Some(PathBuf::from("test-files/visited/milk.txt")));
+    }
+}
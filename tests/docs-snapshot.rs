@@ -10,21 +10,108 @@ USAGE:
     gsub [FLAGS] [OPTIONS] <pattern> <replacement> [--] [files]...
 
 FLAGS:
-    -c, --copy-on-write    Copies files instead of editing them
-    -d, --dry-run          
-        --help             Prints help information
-    -h, --hidden           Do not skip hidden files and directories
-    -V, --version          Prints version information
-    -v, --verbose          
+    -c, --copy-on-write            Copies files instead of editing them
+        --copy-unchanged           Copy unchanged files into --output-dir too. Files gsub doesn't visit (ignored,
+                                   hidden, too big) still aren't copied
+        --count                    Only print how many matches each file has
+    -d, --dry-run                  
+    -l, --files-with-matches       Only print the names of files with matches
+        --follow-named-symlinks    Edit the files that symlinks given as files point to, without following any others
+    -L, --follow-symlinks          Follow symlinks, editing the files they point to
+        --help                     Prints help information
+        --lock                     Take an advisory lock on each file while editing it, skipping files locked by others
+        --no-ignore                Don't skip files ignored by .gitignore, .ignore, .gsubignore or other ignore files
+        --no-ignore-parent         Don't read ignore files in the directories above the files/directories given
+        --no-ignore-vcs            Don't skip files ignored by .gitignore, .git/info/exclude or git's global excludes
+        --no-progress              Don't show a progress display, even when stderr is a terminal
+    -0, --null                     Paths given to --files-from are separated by NUL bytes, instead of newlines
+        --one-file-system          Don't cross into other file systems from the files/directories given
+    -o, --only-output              Print the replacement for each match, instead of editing files
+        --preserve-mtime           Keep the modification times of changed files, and give copies their original's
+        --print0                   Print the changed files to stdout, separated by NUL bytes
+    -q, --quiet                    Only print errors
+    -s, --search                   Print matching lines instead of replacing anything
+    -h, --hidden                   Do not skip hidden files and directories
+        --summary-only             Only print errors, and a summary once finished
+    -u, --unrestricted             Same as --no-ignore. Pass twice to add --hidden. Large files are still skipped, see
+                                   -m
+    -V, --version                  Prints version information
+    -v, --verbose                  Print skipped files too. Pass twice to trace every file visited
 
 OPTIONS:
-    -e, --except <files-to-skip>...                 Files/Directories to skip
+        --color <color>
+            When to use colors and hyperlinks [default: auto]  [possible values: auto, always, never]
+
+    -C, --context <lines>
+            Lines of context to show around each match when searching [default: 0]
+
+        --cow-suffix <suffix>
+            What to add to the end of a file's name to name its copy [default: gsub]
+
+        --except-path <regex>...
+            Files/Directories to skip, by a regex matching their path from the current directory
+
+        --exclude <glob>...
+            Files/Directories to skip, by a gitignore-style glob, e.g. \"/src/generated/\". Globs in .gsubignore files are
+            skipped too
+        --exec-changed <cmd>
+            Command to run on the changed files, e.g. \"rustfmt {}\". Files are passed in batches
+
+        --files-from <path>
+            Read the files/directories to gsub on from a file, one per line. Pass - to use stdin
+
+    -e, --except <files-to-skip>...                 Files/Directories to skip, by a regex matching their names
+        --format <format>
+            How to print results. lsp-workspace-edit and quickfix print the changes for an editor to make, instead of
+            making them [default: text]  [possible values: text, json, lsp-workspace-edit, quickfix]
+        --log-file <path>                           Write everything -vv would print to a file
+        --max-depth <n>
+            Don't descend more than this many directories below the files/directories given
+
     -m, --skip-files-larger-than <max-file-size>    Skip files larger than the given number of bytes [default: 4194304]
+        --on-collision <policy>
+            What to do when a file's copy already exists [default: error]  [possible values: error, overwrite, number]
+
+        --output-dir <dir>
+            Write changed files into a mirror of the files/directories given under dir, instead of editing them
+
+        --outside-symlinks <policy>
+            What --follow-symlinks does with symlinks that point outside the files/directories given [default: skip]
+            [possible values: skip, follow, error]
+        --patch-out <file>
+            Write a patch of every change to a file (or - for stdout) instead of editing anything
+
+        --protect <glob>...
+            Never edit files matching this glob, e.g. \"*.lock\" or \"vendor/**\". Can be repeated
+
+        --sort <sort>
+            Buffer results and print them in order, instead of as soon as they're ready [possible values: path]
+
+    -j, --threads <threads>
+            Number of threads to walk with. 1 walks sequentially, and 0 picks a number for you
+
+        --verify <cmd>
+            Command to run after all edits. If it fails, every edited file is restored
+
 
 ARGS:
     <pattern>        The pattern you want to replace
-    <replacement>    String for replacement
-    <files>...       List of files/directories you want to gsub on. If unspecified, uses the current directory
+    <replacement>    String for replacement. Not needed when searching
+    <files>...       List of files/directories you want to gsub on. If unspecified, uses the current directory, or
+                     stdin if it's piped. Pass - to read stdin and write the result to stdout
+
+SUBCOMMANDS:
+    gsub plan [OPTIONS] <pattern> <replacement> [files]...
+        Print every edit gsub would make as a JSON plan, instead of making them
+    gsub apply [OPTIONS] <plan>
+        Make the edits left in a plan, skipping files that changed since it was made
+    gsub apply-edits [OPTIONS] [edits]
+        Make edits listed as JSON by other tools, or found by `rg --json`
+    gsub cow <accept|discard|diff> [files]...
+        Replace originals with the copies --copy-on-write made, delete the copies, or diff them
+
+    A subcommand's name only counts as one when it comes first. To replace one of the words
+    themselves, put -- or another option before it: gsub -- plan blueprint notes.txt
 "
         .to_string();
 
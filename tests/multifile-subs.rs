@@ -1,7 +1,7 @@
 use std::fs::File;
 use std::io::Write;
 use std::fs;
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 static CONTENTS: &str = "
 My baby takes the moooornin train
@@ -57,3 +57,423 @@ To find me waitin' for him
     cleanup_file("test-files/test_sub_multiple_files_c");
     fs::remove_dir_all("test-files/test_sub_multiple_files").unwrap()
 }
+
+#[test]
+fn test_exec_changed_and_print0() {
+    fs::create_dir_all("test-files/test_exec_changed").expect("unable to create directory");
+    setup_file("test-files/test_exec_changed/a");
+    setup_file("test-files/test_exec_changed/b");
+
+    let output = Command::new("./target/debug/gsub")
+        .arg("moooornin train")
+        .arg("afternoon plane")
+        .arg("test-files/test_exec_changed")
+        .arg("--exec-changed")
+        .arg("sed -i.bak s/nine/ten/ {}")
+        .arg("--print0")
+        .output()
+        .expect("unable to execute gsub");
+
+    let mut printed = String::from_utf8(output.stdout).expect("stdout isn't valid UTF8")
+        .split_terminator('\0')
+        .map(String::from)
+        .collect::<Vec<_>>();
+    printed.sort();
+    assert_eq!(printed, vec![
+        "test-files/test_exec_changed/a",
+        "test-files/test_exec_changed/b",
+    ]);
+
+    let expected = "
+My baby takes the afternoon plane
+He works from ten til five aaaand then
+He takes another home again
+To find me waitin' for him
+";
+    for f in printed {
+        let contents = fs::read_to_string(f).expect("unable to read file");
+        assert_eq!(contents, expected)
+    }
+
+    fs::remove_dir_all("test-files/test_exec_changed").unwrap()
+}
+
+#[test]
+fn test_sorted_output() {
+    fs::create_dir_all("test-files/test_sorted_output/nested").expect("unable to create directory");
+    let files = [
+        "test-files/test_sorted_output/a",
+        "test-files/test_sorted_output/b",
+        "test-files/test_sorted_output/nested/c",
+        "test-files/test_sorted_output/z",
+    ];
+    files.iter().for_each(|f| setup_file(f));
+
+    for threads in &["1", "4"] {
+        let output = Command::new("./target/debug/gsub")
+            .arg("moooornin train")
+            .arg("afternoon plane")
+            .arg("test-files/test_sorted_output")
+            .arg("--dry-run")
+            .arg("--sort")
+            .arg("path")
+            .arg("--threads")
+            .arg(threads)
+            .output()
+            .expect("unable to execute gsub");
+        let expected = files.iter()
+            .map(|f| format!("Would have updated {}\n", f))
+            .collect::<String>();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    fs::remove_dir_all("test-files/test_sorted_output").unwrap()
+}
+
+#[test]
+fn test_files_from_stdin() {
+    fs::create_dir_all("test-files/test_files_from_stdin").expect("unable to create directory");
+    setup_file("test-files/test_files_from_stdin/listed");
+    setup_file("test-files/test_files_from_stdin/not listed");
+
+    let mut child = Command::new("./target/debug/gsub")
+        .args(["--files-from", "-", "-0", "moooornin", "evening"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("unable to execute gsub");
+    child.stdin.take().unwrap()
+        .write_all(b"test-files/test_files_from_stdin/listed\0")
+        .expect("unable to write to gsub");
+    assert!(child.wait().expect("gsub didn't run").success());
+
+    let listed = fs::read_to_string("test-files/test_files_from_stdin/listed").unwrap();
+    let not_listed = fs::read_to_string("test-files/test_files_from_stdin/not listed").unwrap();
+    assert!(listed.contains("the evening train"));
+    assert_eq!(not_listed, CONTENTS);
+
+    fs::remove_dir_all("test-files/test_files_from_stdin").unwrap()
+}
+
+#[test]
+fn test_patch_out() {
+    fs::create_dir_all("test-files/test_patch_out").expect("unable to create directory");
+    setup_file("test-files/test_patch_out/a");
+    setup_file("test-files/test_patch_out/b");
+
+    let output = Command::new("./target/debug/gsub")
+        .args(["--patch-out", "-", "moooornin", "evening", "test-files/test_patch_out"])
+        .output()
+        .expect("unable to execute gsub");
+
+    let patch = String::from_utf8(output.stdout).expect("stdout isn't valid UTF8");
+    let expected_a = "\
+diff --git a/test-files/test_patch_out/a b/test-files/test_patch_out/a
+--- a/test-files/test_patch_out/a
++++ b/test-files/test_patch_out/a
+@@ -1,5 +1,5 @@
+ 
+-My baby takes the moooornin train
++My baby takes the evening train
+ He works from nine til five aaaand then
+ He takes another home again
+ To find me waitin' for him
+";
+    assert!(patch.starts_with(expected_a), "unexpected patch:\n{}", patch);
+    assert!(patch.contains("+++ b/test-files/test_patch_out/b\n"));
+    for f in ["test-files/test_patch_out/a", "test-files/test_patch_out/b"] {
+        assert_eq!(fs::read_to_string(f).unwrap(), CONTENTS);
+    }
+
+    fs::remove_dir_all("test-files/test_patch_out").unwrap()
+}
+
+#[test]
+fn test_output_dir_mirrors_changed_files() {
+    fs::create_dir_all("test-files/test_output_dir/src/nested").expect("unable to create directory");
+    setup_file("test-files/test_output_dir/src/nested/a");
+    fs::write("test-files/test_output_dir/src/b", "unchanged").unwrap();
+
+    let output = Command::new("./target/debug/gsub")
+        .args(["--output-dir", "test-files/test_output_dir/out", "moooornin", "evening"])
+        .arg("test-files/test_output_dir/src")
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+
+    let mirrored = fs::read_to_string("test-files/test_output_dir/out/nested/a")
+        .expect("changed file wasn't mirrored");
+    assert!(mirrored.contains("the evening train"));
+    assert_eq!(fs::read_to_string("test-files/test_output_dir/src/nested/a").unwrap(), CONTENTS);
+    assert!(!fs::exists("test-files/test_output_dir/out/b").unwrap());
+
+    Command::new("./target/debug/gsub")
+        .args(["--output-dir", "test-files/test_output_dir/out", "--copy-unchanged", "moooornin", "evening"])
+        .arg("test-files/test_output_dir/src")
+        .output()
+        .expect("unable to execute gsub");
+    let copied = fs::read_to_string("test-files/test_output_dir/out/b")
+        .expect("unchanged file wasn't copied");
+    assert_eq!(copied, "unchanged");
+
+    fs::remove_dir_all("test-files/test_output_dir").unwrap()
+}
+
+#[test]
+fn test_cow_collisions_and_manifest() {
+    fs::create_dir_all("test-files/test_cow_collisions").expect("unable to create directory");
+    setup_file("test-files/test_cow_collisions/a");
+    setup_file("test-files/test_cow_collisions/notes.gsub");
+    let gsub = fs::canonicalize("./target/debug/gsub").unwrap();
+    let run = |args: &[&str]| Command::new(&gsub)
+        .args(args)
+        .current_dir("test-files/test_cow_collisions")
+        .output()
+        .expect("unable to execute gsub");
+
+    // A file of ours that happens to end in .gsub gets a copy too
+    assert!(run(&["-c", "moooornin", "evening"]).status.success());
+    assert!(fs::exists("test-files/test_cow_collisions/notes.gsub.gsub").unwrap());
+    assert!(fs::exists("test-files/test_cow_collisions/.gsub-manifest").unwrap());
+
+    // gsub's own copies are left alone, and existing ones aren't overwritten
+    let output = run(&["-c", "train", "bus"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("a copy of it already exists"));
+    assert!(!fs::exists("test-files/test_cow_collisions/a.gsub.gsub").unwrap());
+    let copy = fs::read_to_string("test-files/test_cow_collisions/a.gsub").unwrap();
+    assert!(copy.contains("the evening train"));
+
+    assert!(run(&["-c", "--on-collision", "number", "train", "bus"]).status.success());
+    let numbered = fs::read_to_string("test-files/test_cow_collisions/a.1.gsub").unwrap();
+    assert!(numbered.contains("the moooornin bus"));
+
+    // With two copies of a, accepting either would lose the other
+    let output = run(&["cow", "accept"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("it has more than one copy"));
+    assert!(fs::exists("test-files/test_cow_collisions/a.1.gsub").unwrap());
+    assert_eq!(fs::read_to_string("test-files/test_cow_collisions/a").unwrap(), CONTENTS);
+    assert!(run(&["cow", "accept", "a.1.gsub"]).status.success());
+    assert!(fs::read_to_string("test-files/test_cow_collisions/a").unwrap().contains("the moooornin bus"));
+
+    assert!(run(&["cow", "discard"]).status.success());
+    let mut left = fs::read_dir("test-files/test_cow_collisions").unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    left.sort();
+    assert_eq!(left, ["a", "notes.gsub"]);
+
+    fs::remove_dir_all("test-files/test_cow_collisions").unwrap()
+}
+
+#[test]
+fn test_cow_copies_are_recognized_from_anywhere() {
+    fs::create_dir_all("test-files/test_cow_anywhere/sub").expect("unable to create directory");
+    setup_file("test-files/test_cow_anywhere/sub/a");
+    let gsub = fs::canonicalize("./target/debug/gsub").unwrap();
+    let run = |args: &[&str], dir: &str| Command::new(&gsub)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("unable to execute gsub");
+
+    assert!(run(&["-c", "moooornin", "evening", "sub"], "test-files/test_cow_anywhere").status.success());
+    assert!(fs::exists("test-files/test_cow_anywhere/sub/.gsub-manifest").unwrap());
+    let output = run(&["-c", "train", "bus"], "test-files/test_cow_anywhere/sub");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("a copy of it already exists"));
+    assert!(!fs::exists("test-files/test_cow_anywhere/sub/a.gsub.gsub").unwrap());
+
+    // Runs without -c leave gsub's copies alone too
+    let output = run(&["train", "plane", "."], "test-files/test_cow_anywhere");
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains(".gsub"));
+    assert!(fs::read_to_string("test-files/test_cow_anywhere/sub/a").unwrap().contains("plane"));
+
+    // A manifest that can't be read is warned about, not fatal
+    fs::write("test-files/test_cow_anywhere/sub/.gsub-manifest", "not json\n").unwrap();
+    let output = run(&["-c", "--on-collision", "number", "train", "bus"], "test-files/test_cow_anywhere");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Ignoring line 1 of"));
+
+    fs::remove_dir_all("test-files/test_cow_anywhere").unwrap()
+}
+
+#[test]
+fn test_each_file_is_edited_once() {
+    fs::create_dir_all("test-files/test_edited_once").expect("unable to create directory");
+    setup_file("test-files/test_edited_once/a");
+    fs::hard_link("test-files/test_edited_once/a", "test-files/test_edited_once/b").unwrap();
+
+    let output = Command::new("./target/debug/gsub")
+        .args(["-v", "--threads", "1", "moooornin", "evening"])
+        .args(["test-files/test_edited_once", "test-files/test_edited_once/a", "test-files/test_edited_once/"])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stdout.matches("Updated").count(), 1);
+    assert_eq!(stderr.matches("because it's the same file as").count(), 3);
+
+    fs::remove_dir_all("test-files/test_edited_once").unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_following() {
+    use std::os::unix::fs::symlink;
+    fs::create_dir_all("test-files/test_symlinks/root/d").expect("unable to create directory");
+    fs::create_dir_all("test-files/test_symlinks/outside").expect("unable to create directory");
+    setup_file("test-files/test_symlinks/outside/o");
+    symlink("../../outside/o", "test-files/test_symlinks/root/d/out").unwrap();
+    symlink("..", "test-files/test_symlinks/root/d/loop").unwrap();
+    let run = |args: &[&str], root: &str| Command::new("./target/debug/gsub")
+        .args(["-v", "--dry-run"])
+        .args(args)
+        .args(["moooornin", "evening", root])
+        .output()
+        .expect("unable to execute gsub");
+
+    let output = run(&[], "test-files/test_symlinks/root");
+    assert!(output.stdout.is_empty(), "symlinks shouldn't be followed by default");
+
+    let output = run(&["-L"], "test-files/test_symlinks/root");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.stdout.is_empty(), "symlinks out of the root shouldn't be followed by default");
+    assert!(stderr.contains("File system loop found"));
+
+    let output = run(&["-L", "--outside-symlinks", "error"], "test-files/test_symlinks/root");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipping test-files/test_symlinks/root/d/out because it's a symlink"));
+    assert!(!output.status.success(), "refusing a symlink should fail the run");
+
+    let output = run(&["-L", "--outside-symlinks", "follow"], "test-files/test_symlinks/root");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Would have updated test-files/test_symlinks/root/d/out"));
+
+    let output = run(&[], "test-files/test_symlinks/root/d/out");
+    assert!(output.stdout.is_empty(), "named symlinks shouldn't be followed by default");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pass --follow-named-symlinks"));
+    Command::new("./target/debug/gsub")
+        .args(["--follow-named-symlinks", "moooornin", "evening", "test-files/test_symlinks/root/d/out"])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(fs::read_to_string("test-files/test_symlinks/outside/o").unwrap().contains("the evening train"));
+    assert!(fs::symlink_metadata("test-files/test_symlinks/root/d/out").unwrap().file_type().is_symlink());
+
+    fs::remove_dir_all("test-files/test_symlinks").unwrap()
+}
+
+#[test]
+fn test_protected_paths_are_never_edited() {
+    fs::create_dir_all("test-files/test_protected/.git").expect("unable to create directory");
+    fs::create_dir_all("test-files/test_protected/vendor/lib").expect("unable to create directory");
+    for f in ["a", ".git/config", "vendor/lib/v", "Cargo.lock"] {
+        setup_file(&format!("test-files/test_protected/{}", f));
+    }
+
+    let output = Command::new("./target/debug/gsub")
+        .args(["--hidden", "--protect", "*.lock", "--protect", "vendor/**", "moooornin", "evening"])
+        .arg("test-files/test_protected")
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+
+    let read = |f: &str| fs::read_to_string(format!("test-files/test_protected/{}", f)).unwrap();
+    assert!(read("a").contains("the evening train"));
+    assert_eq!(read(".git/config"), CONTENTS);
+    assert_eq!(read("vendor/lib/v"), CONTENTS);
+    assert_eq!(read("Cargo.lock"), CONTENTS);
+
+    fs::remove_dir_all("test-files/test_protected").unwrap()
+}
+
+#[test]
+fn test_path_exclusions_and_gsubignore() {
+    fs::create_dir_all("test-files/test_path_exclusions/src/generated").expect("unable to create directory");
+    fs::create_dir_all("test-files/test_path_exclusions/lib/generated").expect("unable to create directory");
+    for f in ["src/generated/a", "lib/generated/b", "src/c.txt", "src/d.bak", "e"] {
+        setup_file(&format!("test-files/test_path_exclusions/{}", f));
+    }
+    fs::write("test-files/test_path_exclusions/src/.gsubignore", "*.bak\n").unwrap();
+
+    let output = Command::new("./target/debug/gsub")
+        .args(["--except-path", "^test-files/test_path_exclusions/src/generated$"])
+        .args(["--exclude", "/test-files/test_path_exclusions/src/*.txt", "moooornin", "evening"])
+        .arg("test-files/test_path_exclusions")
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+
+    let changed = |f: &str| {
+        let contents = fs::read_to_string(format!("test-files/test_path_exclusions/{}", f)).unwrap();
+        contents != CONTENTS
+    };
+    assert!(!changed("src/generated/a"));
+    assert!(changed("lib/generated/b"));
+    assert!(!changed("src/c.txt"));
+    assert!(!changed("src/d.bak"));
+    assert!(changed("e"));
+
+    fs::remove_dir_all("test-files/test_path_exclusions").unwrap()
+}
+
+#[test]
+fn test_ignore_file_handling() {
+    fs::create_dir_all("test-files/test_ignore_files/built").expect("unable to create directory");
+    fs::create_dir_all("test-files/test_ignore_files/tmp").expect("unable to create directory");
+    fs::create_dir_all("test-files/test_ignore_files/a/b").expect("unable to create directory");
+    fs::write("test-files/test_ignore_files/.gitignore", "built/\n").unwrap();
+    fs::write("test-files/test_ignore_files/.ignore", "tmp/\n").unwrap();
+    for f in ["built/x", "tmp/y", "a/b/z", ".hidden"] {
+        setup_file(&format!("test-files/test_ignore_files/{}", f));
+    }
+    let would_update = |args: &[&str]| {
+        let output = Command::new("./target/debug/gsub")
+            .args(["--dry-run", "--sort", "path"])
+            .args(args)
+            .args(["moooornin", "evening", "test-files/test_ignore_files"])
+            .output()
+            .expect("unable to execute gsub");
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.trim_start_matches("Would have updated test-files/test_ignore_files/").to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(would_update(&[]), ["a/b/z"]);
+    assert_eq!(would_update(&["--no-ignore-vcs"]), ["a/b/z", "built/x"]);
+    assert_eq!(would_update(&["-u"]), ["a/b/z", "built/x", "tmp/y"]);
+    assert_eq!(would_update(&["-uu"]), [".hidden", "a/b/z", "built/x", "tmp/y"]);
+    assert_eq!(would_update(&["-u", "--max-depth", "2"]), ["built/x", "tmp/y"]);
+
+    fs::remove_dir_all("test-files/test_ignore_files").unwrap()
+}
+
+#[test]
+fn test_zero_threads_picks_a_number() {
+    fs::create_dir_all("test-files/test_zero_threads").expect("unable to create directory");
+    setup_file("test-files/test_zero_threads/a");
+    let mut child = Command::new("./target/debug/gsub")
+        .args(["--threads", "0", "--files-from", "-", "moooornin", "evening"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("unable to execute gsub");
+    child.stdin.take().unwrap().write_all(b"test-files/test_zero_threads/a\n").unwrap();
+    // No walker threads would mean waiting forever
+    let started = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed().as_secs() > 10 {
+            child.kill().unwrap();
+            panic!("gsub hung with --threads 0");
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    };
+    assert!(status.success());
+
+    let contents = fs::read_to_string("test-files/test_zero_threads/a").unwrap();
+    assert!(contents.contains("the evening train"));
+    fs::remove_dir_all("test-files/test_zero_threads").unwrap()
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::Write;
+use std::fs;
+use std::process::Command;
+use serde_json::Value;
+
+static CONTENTS: &str = "
+Peter Piper picked a peck of pickled peppers
+A peck of pickled peppers Peter Piper picked
+";
+
+fn setup_file(name: &str) {
+    assert!(name.starts_with("test-files/"));
+    File::create(name)
+        .expect("couldn't setup test file")
+        .write_all(CONTENTS.as_bytes())
+        .expect("couldn't write to test file")
+}
+
+fn cleanup_file(name: &str) {
+    assert!(name.starts_with("test-files/"));
+    fs::remove_file(name).expect("failed to delete file");
+}
+
+fn plan(file: &str) -> Value {
+    let output = Command::new("./target/debug/gsub")
+        .args(["plan", "pickled", "pickleless", file])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).expect("plan isn't valid JSON")
+}
+
+fn apply(plan_file: &str) -> bool {
+    Command::new("./target/debug/gsub")
+        .args(["apply", plan_file])
+        .output()
+        .expect("unable to execute gsub")
+        .status
+        .success()
+}
+
+#[test]
+fn test_applies_reviewed_plan() {
+    setup_file("test-files/plan-reviewed");
+    let mut plan = plan("test-files/plan-reviewed");
+    assert_eq!(fs::read_to_string("test-files/plan-reviewed").unwrap(), CONTENTS);
+
+    // The reviewer only wants the first edit
+    plan["files"][0]["edits"].as_array_mut().unwrap().truncate(1);
+    fs::write("test-files/plan-reviewed.json", plan.to_string()).unwrap();
+    assert!(apply("test-files/plan-reviewed.json"));
+
+    let expected = "
+Peter Piper picked a peck of pickleless peppers
+A peck of pickled peppers Peter Piper picked
+";
+    assert_eq!(fs::read_to_string("test-files/plan-reviewed").unwrap(), expected);
+    cleanup_file("test-files/plan-reviewed");
+    cleanup_file("test-files/plan-reviewed.json");
+}
+
+#[test]
+fn test_refuses_files_changed_since_planning() {
+    setup_file("test-files/plan-stale");
+    let plan = plan("test-files/plan-stale");
+    fs::write("test-files/plan-stale.json", plan.to_string()).unwrap();
+    fs::write("test-files/plan-stale", "Peter Piper picked some pickled peppers").unwrap();
+
+    assert!(!apply("test-files/plan-stale.json"));
+    let contents = fs::read_to_string("test-files/plan-stale").unwrap();
+    assert_eq!(contents, "Peter Piper picked some pickled peppers");
+    cleanup_file("test-files/plan-stale");
+    cleanup_file("test-files/plan-stale.json");
+}
+
+#[test]
+fn test_applies_external_edits() {
+    setup_file("test-files/external-edits");
+    let edits = r#"[
+        {"path": "test-files/external-edits", "start": 1, "end": 6, "replacement": "Pete"},
+        {"path": "test-files/external-edits", "start": {"line": 3, "column": 27}, "end": {"line": 3, "column": 32}, "replacement": "Pete"}
+    ]"#;
+    fs::write("test-files/external-edits.json", edits).unwrap();
+    let output = Command::new("./target/debug/gsub")
+        .args(["apply-edits", "test-files/external-edits.json"])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+
+    let expected = "
+Pete Piper picked a peck of pickled peppers
+A peck of pickled peppers Pete Piper picked
+";
+    assert_eq!(fs::read_to_string("test-files/external-edits").unwrap(), expected);
+    cleanup_file("test-files/external-edits");
+    cleanup_file("test-files/external-edits.json");
+}
+
+#[test]
+fn test_replaces_the_word_plan_after_double_dash() {
+    fs::write("test-files/plan-escaped", "the plan is to plan").unwrap();
+    let output = Command::new("./target/debug/gsub")
+        .args(["--", "plan", "blueprint", "test-files/plan-escaped"])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+    assert!(serde_json::from_slice::<Value>(&output.stdout).is_err());
+
+    let contents = fs::read_to_string("test-files/plan-escaped").unwrap();
+    assert_eq!(contents, "the blueprint is to blueprint");
+    cleanup_file("test-files/plan-escaped");
+}
+
+#[test]
+fn test_refuses_edits_to_protected_files() {
+    fs::create_dir_all("test-files/edits-protected/.git").unwrap();
+    setup_file("test-files/edits-protected/.git/config");
+    setup_file("test-files/edits-protected/Cargo.lock");
+    let edits = r#"{"type":"match","data":{"path":{"text":".git/config"},"absolute_offset":1,"submatches":[{"start":0,"end":5}]}}
+{"type":"match","data":{"path":{"text":"Cargo.lock"},"absolute_offset":1,"submatches":[{"start":0,"end":5}]}}
+"#;
+    fs::write("test-files/edits-protected/edits.json", edits).unwrap();
+    let gsub = fs::canonicalize("./target/debug/gsub").unwrap();
+    let output = Command::new(&gsub)
+        .args(["apply-edits", "--protect", "*.lock", "-r", "Pete", "edits.json"])
+        .current_dir("test-files/edits-protected")
+        .output()
+        .expect("unable to execute gsub");
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("because it resolves to somewhere protected").count(), 2);
+    assert_eq!(fs::read_to_string("test-files/edits-protected/.git/config").unwrap(), CONTENTS);
+    assert_eq!(fs::read_to_string("test-files/edits-protected/Cargo.lock").unwrap(), CONTENTS);
+    fs::remove_dir_all("test-files/edits-protected").unwrap();
+}
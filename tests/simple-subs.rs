@@ -1,7 +1,8 @@
-use std::fs::File;
+use std::fs::{File, FileTimes};
 use std::io::Write;
 use std::fs;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
 
 static CONTENTS: &str = "
 How much wood
@@ -72,3 +73,139 @@ fn test_no_subs() {
     assert_eq!(file_contents, CONTENTS);
     cleanup_file("test-files/no-subs");
 }
+
+#[test]
+fn test_summary_only_with_log_file() {
+    setup_file("test-files/summary-only");
+    let output = Command::new("./target/debug/gsub")
+        .arg("wood")
+        .arg("would")
+        .arg("test-files/summary-only")
+        .arg("--summary-only")
+        .arg("--log-file")
+        .arg("test-files/summary-only.log")
+        .output()
+        .expect("unable to execute gsub");
+    let stdout = String::from_utf8(output.stdout).expect("stdout isn't valid UTF8");
+    assert_eq!(stdout, "1 changed (4 replacements), 0 unchanged, 0 skipped, 0 failed\n");
+    let log = fs::read_to_string("test-files/summary-only.log").expect("unable to read log");
+    assert_eq!(log, "\
+Visiting test-files/summary-only
+Updated test-files/summary-only
+1 changed (4 replacements), 0 unchanged, 0 skipped, 0 failed
+");
+    cleanup_file("test-files/summary-only");
+    cleanup_file("test-files/summary-only.log");
+}
+
+#[test]
+fn test_search_only() {
+    setup_file("test-files/search-only");
+    let output = Command::new("./target/debug/gsub")
+        .arg("--search")
+        .arg("chuck c")
+        .arg("test-files/search-only")
+        .output()
+        .expect("unable to execute gsub");
+    let stdout = String::from_utf8(output.stdout).expect("stdout isn't valid UTF8");
+    assert_eq!(stdout, "\
+test-files/search-only:3:14:could a wood chuck chuck
+test-files/search-only:4:11:if a wood chuck could chuck
+");
+    let file_contents = fs::read_to_string("test-files/search-only").expect("unable to read file");
+    assert_eq!(file_contents, CONTENTS);
+    cleanup_file("test-files/search-only");
+}
+
+#[test]
+fn test_filters_stdin_to_stdout() {
+    let mut child = Command::new("./target/debug/gsub")
+        .arg("wood")
+        .arg("would")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("unable to execute gsub");
+    child.stdin.take().unwrap().write_all(CONTENTS.as_bytes()).expect("couldn't write to stdin");
+    let output = child.wait_with_output().expect("gsub didn't finish");
+    let expected = "
+How much would
+could a would chuck chuck
+if a would chuck could chuck
+would
+";
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn test_quickfix_format() {
+    setup_file("test-files/quickfix");
+    let output = Command::new("./target/debug/gsub")
+        .args(["--format", "quickfix", "chuck", "throw", "test-files/quickfix"])
+        .output()
+        .expect("unable to execute gsub");
+
+    let expected = "\
+test-files/quickfix:3:14: replace `chuck` with `throw`
+test-files/quickfix:3:20: replace `chuck` with `throw`
+test-files/quickfix:4:11: replace `chuck` with `throw`
+test-files/quickfix:4:23: replace `chuck` with `throw`
+";
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    assert_eq!(fs::read_to_string("test-files/quickfix").unwrap(), CONTENTS);
+    cleanup_file("test-files/quickfix");
+}
+
+#[test]
+fn test_skips_files_locked_by_others() {
+    setup_file("test-files/locked-by-others");
+    let held = File::open("test-files/locked-by-others").unwrap();
+    held.lock().expect("couldn't lock test file");
+    let output = Command::new("./target/debug/gsub")
+        .args(["--lock", "wood", "would", "test-files/locked-by-others"])
+        .output()
+        .expect("unable to execute gsub");
+    held.unlock().unwrap();
+    let file_contents = fs::read_to_string("test-files/locked-by-others").expect("unable to read file");
+    cleanup_file("test-files/locked-by-others");
+    assert_eq!(file_contents, CONTENTS);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("because it is locked by another process"));
+}
+
+#[test]
+fn test_refuses_file_flags_when_filtering_stdin() {
+    let mut child = Command::new("./target/debug/gsub")
+        .args(["--dry-run", "wood", "would"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("unable to execute gsub");
+    // gsub may exit before reading any of it
+    let _ = child.stdin.take().unwrap().write_all(CONTENTS.as_bytes());
+    let output = child.wait_with_output().expect("gsub didn't finish");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Pass . to work on the current directory"));
+}
+
+#[test]
+fn test_preserves_mtime_in_place() {
+    setup_file("test-files/simple-subs-mtime");
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+    File::options().write(true).open("test-files/simple-subs-mtime").unwrap()
+        .set_times(FileTimes::new().set_modified(an_hour_ago))
+        .unwrap();
+    let output = Command::new("./target/debug/gsub")
+        .args(["--preserve-mtime", "wood", "would", "test-files/simple-subs-mtime"])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+
+    let md = fs::metadata("test-files/simple-subs-mtime").unwrap();
+    let contents = fs::read_to_string("test-files/simple-subs-mtime").unwrap();
+    cleanup_file("test-files/simple-subs-mtime");
+    assert!(contents.contains("How much would"));
+    assert_eq!(md.modified().unwrap(), an_hour_ago);
+}
@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::Write;
+use std::fs;
+use std::process::Command;
+
+static CONTENTS: &str = "
+Peter Piper picked a peck of pickled peppers
+";
+
+fn setup_file(name: &str) {
+    assert!(name.starts_with("test-files/"));
+    File::create(name)
+        .expect("couldn't setup test file")
+        .write_all(CONTENTS.as_bytes())
+        .expect("couldn't write to test file")
+}
+
+fn cleanup_file(name: &str) {
+    assert!(name.starts_with("test-files/"));
+    fs::remove_file(name).expect("failed to delete file");
+}
+
+#[test]
+fn test_failed_verification_rolls_back() {
+    setup_file("test-files/verify-fails");
+    let output = Command::new("./target/debug/gsub")
+        .arg("pickled")
+        .arg("pickleless")
+        .arg("test-files/verify-fails")
+        .arg("--verify")
+        .arg("exit 3")
+        .output()
+        .expect("unable to execute gsub");
+    assert!(!output.status.success());
+    let file_contents = fs::read_to_string("test-files/verify-fails").expect("unable to read file");
+    assert_eq!(file_contents, CONTENTS);
+    cleanup_file("test-files/verify-fails");
+}
+
+#[test]
+fn test_successful_verification_keeps_changes() {
+    setup_file("test-files/verify-succeeds");
+    let output = Command::new("./target/debug/gsub")
+        .arg("pickled")
+        .arg("pickleless")
+        .arg("test-files/verify-succeeds")
+        .arg("--verify")
+        .arg("grep -q pickleless test-files/verify-succeeds")
+        .output()
+        .expect("unable to execute gsub");
+    assert!(output.status.success());
+    let file_contents = fs::read_to_string("test-files/verify-succeeds")
+        .expect("unable to read file");
+    assert_eq!(file_contents, "
+Peter Piper picked a peck of pickleless peppers
+");
+    cleanup_file("test-files/verify-succeeds");
+}
+
+#[test]
+fn test_missing_verify_command_rolls_back() {
+    setup_file("test-files/verify-missing");
+    let output = Command::new("./target/debug/gsub")
+        .args(["pickled", "pickleless", "test-files/verify-missing"])
+        .args(["--verify", "./gsub-verify-command-that-does-not-exist"])
+        .output()
+        .expect("unable to execute gsub");
+    assert!(!output.status.success());
+    let file_contents = fs::read_to_string("test-files/verify-missing").expect("unable to read file");
+    assert_eq!(file_contents, CONTENTS);
+    cleanup_file("test-files/verify-missing");
+}